@@ -0,0 +1,68 @@
+// Instructions per second with headless peripherals, with the peripherals
+// known at compile time and behind trait objects. Run with `cargo bench`.
+
+use std::time::Instant;
+
+use rand::{rngs::StdRng, SeedableRng};
+use koro8::cpu::{Builder, Cpu};
+use koro8::arch::{Display, Keyboard, Buzzer, Listener};
+
+const INSTRUCTIONS: u64 = 20_000_000;
+
+// Adds, shifts and jumps, no peripherals involved
+const ARITHMETIC: [u8; 16] = [
+    0x60, 0x00, // 200: V0 = 0
+    0x61, 0x03, // 202: V1 = 3
+    0x70, 0x01, // 204: V0 += 1
+    0x80, 0x14, // 206: V0 += V1
+    0x82, 0x00, // 208: V2 = V0
+    0x82, 0x06, // 20A: V2 >>= 1
+    0x82, 0x0E, // 20C: V2 <<= 1
+    0x12, 0x04  // 20E: jump to 204
+];
+
+// Draws a font sprite all over the screen
+const DRAWING: [u8; 10] = [
+    0xA0, 0x00, // 200: I = font 0
+    0xD0, 0x15, // 202: draw 8x5 at V0, V1
+    0x70, 0x07, // 204: V0 += 7
+    0x71, 0x03, // 206: V1 += 3
+    0x12, 0x02  // 208: jump to 202
+];
+
+// Checks keys and rolls random numbers. Nothing is pressed on the headless
+// keyboard, so the second check always skips, and the jump back follows either way.
+const KEYS_AND_RANDOM: [u8; 10] = [
+    0xE0, 0x9E, // 200: skip if key V0 is pressed
+    0xC1, 0xFF, // 202: V1 = random
+    0xE1, 0xA1, // 204: skip if key V1 isn't pressed
+    0x60, 0x00, // 206: V0 = 0
+    0x12, 0x00  // 208: jump to 200
+];
+
+fn measure<D: Display, K: Keyboard, B: Buzzer, R: rand::RngCore, L: Listener>(cpu: &mut Cpu<D, K, B, R, L>) -> f64 {
+    let start = Instant::now();
+    for _ in 0..INSTRUCTIONS {
+        cpu.step();
+    }
+    INSTRUCTIONS as f64 / start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let roms: [(&str, &[u8]); 3] = [
+        ("arithmetic", &ARITHMETIC),
+        ("drawing", &DRAWING),
+        ("keys and random", &KEYS_AND_RANDOM)
+    ];
+    for (name, rom) in roms.iter() {
+        let builder = Builder::new().rng(StdRng::seed_from_u64(0)).rom(rom);
+        let mut generic = builder.build().unwrap();
+        let mut erased = Builder::new().rng(StdRng::seed_from_u64(0)).rom(rom).erase().build().unwrap();
+        println!(
+            "{:<16} {:>8.1} M instructions/s generic, {:>8.1} M erased",
+            name,
+            measure(&mut generic) / 1e6,
+            measure(&mut erased) / 1e6
+        );
+    }
+}
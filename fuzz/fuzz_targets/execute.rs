@@ -0,0 +1,15 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rand::{rngs::StdRng, SeedableRng};
+
+const CYCLES: usize = 10_000;
+
+fuzz_target!(|rom: &[u8]| {
+    let mut cpu = match koro8::cpu::Builder::new().rng(StdRng::seed_from_u64(0)).speed(9).rom(rom).build() {
+        Ok(cpu) => cpu,
+        Err(_) => return
+    };
+    for _ in 0..CYCLES {
+        cpu.step();
+    }
+});
@@ -0,0 +1,14 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rand::{rngs::StdRng, SeedableRng};
+
+fuzz_target!(|rom: &[u8]| {
+    let mut cpu = koro8::cpu::Builder::new()
+        .rng(StdRng::seed_from_u64(0))
+        .speed(9)
+        .build()
+        .unwrap();
+    if cpu.load(rom).is_ok() {
+        cpu.reset();
+    }
+});
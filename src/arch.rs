@@ -1,28 +1,263 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+
 pub const NUM_KEYS: usize = 16;
 pub const SCALE: usize = 16;
 pub const WIDTH: usize = 64;
 pub const HEIGHT: usize = 32;
-pub const PIXELS: usize = WIDTH as usize * HEIGHT as usize;
+pub const PIXELS: usize = WIDTH * HEIGHT;
 
 pub struct Sprite<'a>(pub &'a [u8]);
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RunState {
+    Running,
+    Paused,
+    FastForward,
+    SlowMotion
+}
+
+// How the screen is fitted into a window of any size, keeping its aspect ratio
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scaling {
+    // Largest whole multiple of the screen size that fits, for crisp square pixels
+    Integer,
+    // Fills the window, pixels may differ in size by one
+    Nearest,
+    // Fills the window, images are filtered
+    Smooth
+}
+
+impl FromStr for Scaling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Scaling, String> {
+        match s {
+            "integer" => Ok(Scaling::Integer),
+            "nearest" => Ok(Scaling::Nearest),
+            "smooth" => Ok(Scaling::Smooth),
+            _ => Err(String::from("must be integer, nearest or smooth"))
+        }
+    }
+}
+
+// How a buzzer that plays sound clips follows the sound timer
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum SoundMode {
+    // A square wave tone for as long as the sound timer runs, like the original
+    Tone,
+    // A clip that fades out when the sound timer runs out
+    Truncate,
+    // A clip that plays to the end, however short the sound was meant to be
+    Complete
+}
+
+impl FromStr for SoundMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SoundMode, String> {
+        match s {
+            "tone" => Ok(SoundMode::Tone),
+            "truncate" => Ok(SoundMode::Truncate),
+            "complete" => Ok(SoundMode::Complete),
+            _ => Err(String::from("must be tone, truncate or complete"))
+        }
+    }
+}
+
+// Shape of a synthesized tone
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Waveform {
+    Square,
+    Sine
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Waveform, String> {
+        match s {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            _ => Err(String::from("must be square or sine"))
+        }
+    }
+}
+
+// Things happening in a rom that peripherals may want to react to
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Event {
+    // A sprite was drawn over lit pixels
+    Collision,
+    Clear,
+    // The rom started waiting for a key press
+    KeyWait,
+    Reset,
+    InvalidInstruction
+}
+
+// What changed in the machine's state. The program counter advancing and the
+// timers counting down aren't changes, only setting them is.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Change {
+    // `len` bytes from `addr` on, wrapping around at the end of memory
+    Memory { addr: u16, len: usize },
+    // V0 to VF
+    Register(u8),
+    I,
+    Pc,
+    // The stack pointer or entries
+    Stack,
+    DelayTimer,
+    SoundTimer
+}
+
+impl FromStr for Event {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Event, String> {
+        match s {
+            "collision" => Ok(Event::Collision),
+            "clear" => Ok(Event::Clear),
+            "key-wait" => Ok(Event::KeyWait),
+            "reset" => Ok(Event::Reset),
+            "invalid-instruction" => Ok(Event::InvalidInstruction),
+            _ => Err(format!("unknown event {} (must be collision, clear, key-wait, reset or invalid-instruction)", s))
+        }
+    }
+}
+
+// Shows the screen the CPU drew, see crate::framebuffer.
 pub trait Display {
-    fn clear(&mut self);
-    fn draw(&mut self, sprite: &Sprite, x: u8, y: u8) -> bool;
-    fn present(&mut self);
+    // Called once per frame with the pixels row by row, whether they changed or not.
+    fn present(&mut self, pixels: &[bool;PIXELS]);
+    fn show_run_state(&mut self, state: RunState);
+    fn show_speed(&mut self, instructions_per_frame: u64);
+    // Title of the rom if it's a known one, see crate::database
+    fn show_title(&mut self, title: Option<&str>);
+    // What the rom's keys do, like "left = 4, right = 6", if the database knows
+    fn show_key_hints(&mut self, _hints: Option<&str>) { }
+    fn next_theme(&mut self);
+    fn toggle_fullscreen(&mut self);
     fn reset(&mut self);
 }
 
 pub trait Keyboard {
     fn pressed(&mut self, key: u8) -> bool;
     fn wait_key(&mut self) -> u8;
-    fn reset_signal(&mut self) -> bool;
-    fn power_off_signal(&mut self) -> bool;
+    // Hotkeys, each true once per press, and whether fast-forward is held.
+    // Keyboards without them can leave them out.
+    fn reset_signal(&mut self) -> bool { false }
+    fn power_off_signal(&mut self) -> bool { false }
+    fn pause_signal(&mut self) -> bool { false }
+    fn frame_advance_signal(&mut self) -> bool { false }
+    fn slow_motion_signal(&mut self) -> bool { false }
+    fn speed_up_signal(&mut self) -> bool { false }
+    fn speed_down_signal(&mut self) -> bool { false }
+    fn theme_signal(&mut self) -> bool { false }
+    fn fullscreen_signal(&mut self) -> bool { false }
+    fn mute_signal(&mut self) -> bool { false }
+    fn volume_up_signal(&mut self) -> bool { false }
+    fn volume_down_signal(&mut self) -> bool { false }
+    fn fast_forward_held(&mut self) -> bool { false }
     fn reset(&mut self);
 }
 
+// Started when the sound timer is set while it's zero, stopped when it runs out or is set to zero.
 pub trait Buzzer {
-    fn start(&mut self);
+    // `frames` is the sound timer value the sound was started with, in 60 Hz frames.
+    fn start(&mut self, frames: u8);
     fn stop(&mut self);
+    // Volume from 0 to 100 percent
+    fn set_volume(&mut self, percent: u8);
+    fn volume(&self) -> u8;
+    fn set_muted(&mut self, muted: bool);
+    fn muted(&self) -> bool;
     fn reset(&mut self);
 }
+
+pub trait Listener {
+    fn notify(&mut self, event: Event);
+
+    // Called after the rom or the CPU's API changed memory or registers.
+    fn changed(&mut self, _change: Change) { }
+}
+
+// Boxed peripherals, so that a CPU can be made of trait objects picked at runtime.
+impl <T: Display + ?Sized> Display for Box<T> {
+    fn present(&mut self, pixels: &[bool;PIXELS]) { (**self).present(pixels) }
+    fn show_run_state(&mut self, state: RunState) { (**self).show_run_state(state) }
+    fn show_speed(&mut self, instructions_per_frame: u64) { (**self).show_speed(instructions_per_frame) }
+    fn show_title(&mut self, title: Option<&str>) { (**self).show_title(title) }
+    fn show_key_hints(&mut self, hints: Option<&str>) { (**self).show_key_hints(hints) }
+    fn next_theme(&mut self) { (**self).next_theme() }
+    fn toggle_fullscreen(&mut self) { (**self).toggle_fullscreen() }
+    fn reset(&mut self) { (**self).reset() }
+}
+
+impl <T: Keyboard + ?Sized> Keyboard for Box<T> {
+    fn pressed(&mut self, key: u8) -> bool { (**self).pressed(key) }
+    fn wait_key(&mut self) -> u8 { (**self).wait_key() }
+    fn reset_signal(&mut self) -> bool { (**self).reset_signal() }
+    fn power_off_signal(&mut self) -> bool { (**self).power_off_signal() }
+    fn pause_signal(&mut self) -> bool { (**self).pause_signal() }
+    fn frame_advance_signal(&mut self) -> bool { (**self).frame_advance_signal() }
+    fn slow_motion_signal(&mut self) -> bool { (**self).slow_motion_signal() }
+    fn speed_up_signal(&mut self) -> bool { (**self).speed_up_signal() }
+    fn speed_down_signal(&mut self) -> bool { (**self).speed_down_signal() }
+    fn theme_signal(&mut self) -> bool { (**self).theme_signal() }
+    fn fullscreen_signal(&mut self) -> bool { (**self).fullscreen_signal() }
+    fn mute_signal(&mut self) -> bool { (**self).mute_signal() }
+    fn volume_up_signal(&mut self) -> bool { (**self).volume_up_signal() }
+    fn volume_down_signal(&mut self) -> bool { (**self).volume_down_signal() }
+    fn fast_forward_held(&mut self) -> bool { (**self).fast_forward_held() }
+    fn reset(&mut self) { (**self).reset() }
+}
+
+impl <T: Buzzer + ?Sized> Buzzer for Box<T> {
+    fn start(&mut self, frames: u8) { (**self).start(frames) }
+    fn stop(&mut self) { (**self).stop() }
+    fn set_volume(&mut self, percent: u8) { (**self).set_volume(percent) }
+    fn volume(&self) -> u8 { (**self).volume() }
+    fn set_muted(&mut self, muted: bool) { (**self).set_muted(muted) }
+    fn muted(&self) -> bool { (**self).muted() }
+    fn reset(&mut self) { (**self).reset() }
+}
+
+impl <T: Listener + ?Sized> Listener for Box<T> {
+    fn notify(&mut self, event: Event) { (**self).notify(event) }
+    fn changed(&mut self, change: Change) { (**self).changed(change) }
+}
+
+// Nobody listening
+impl Listener for () {
+    fn notify(&mut self, _event: Event) { }
+}
+
+impl <T: Listener> Listener for Option<T> {
+    fn notify(&mut self, event: Event) {
+        if let Some(listener) = self {
+            listener.notify(event);
+        }
+    }
+
+    fn changed(&mut self, change: Change) {
+        if let Some(listener) = self {
+            listener.changed(change);
+        }
+    }
+}
+
+impl <T: Listener> Listener for Vec<T> {
+    fn notify(&mut self, event: Event) {
+        self.iter_mut().for_each(|listener| listener.notify(event));
+    }
+
+    fn changed(&mut self, change: Change) {
+        self.iter_mut().for_each(|listener| listener.changed(change));
+    }
+}
@@ -0,0 +1,47 @@
+// Graphics, sounds and rom data built into the executable, so that koro8 runs from
+// anywhere without being pointed at its asset directories.
+
+// File names with their contents
+pub type Files = &'static [(&'static str, &'static [u8])];
+
+// Files of the bundled themes by theme name
+pub const THEMES: [(&str, Files); 4] = [
+    ("korone", &[
+        ("theme.toml", include_bytes!("../themes/korone/theme.toml")),
+        ("pixel.png", include_bytes!("../themes/korone/pixel.png")),
+        ("background.png", include_bytes!("../themes/korone/background.png"))
+    ]),
+    ("monochrome", &[("theme.toml", include_bytes!("../themes/monochrome/theme.toml"))]),
+    ("amber", &[("theme.toml", include_bytes!("../themes/amber/theme.toml"))]),
+    ("lcd", &[("theme.toml", include_bytes!("../themes/lcd/theme.toml"))])
+];
+
+// The Korone sounds and their sound pack manifest
+pub const SOUNDS: [(&str, &[u8]); 13] = [
+    ("sounds.toml", include_bytes!("../sounds/sounds.toml")),
+    ("assassin.mp3", include_bytes!("../sounds/assassin.mp3")),
+    ("ehehu.mp3", include_bytes!("../sounds/ehehu.mp3")),
+    ("goddamn.mp3", include_bytes!("../sounds/goddamn.mp3")),
+    ("orayo1.mp3", include_bytes!("../sounds/orayo1.mp3")),
+    ("orayo2.mp3", include_bytes!("../sounds/orayo2.mp3")),
+    ("orayo3.mp3", include_bytes!("../sounds/orayo3.mp3")),
+    ("pyonpyon.mp3", include_bytes!("../sounds/pyonpyon.mp3")),
+    ("sneeze.mp3", include_bytes!("../sounds/sneeze.mp3")),
+    ("sneeze2.mp3", include_bytes!("../sounds/sneeze2.mp3")),
+    ("yes.mp3", include_bytes!("../sounds/yes.mp3")),
+    ("yubi.mp3", include_bytes!("../sounds/yubi.mp3")),
+    ("yubiyubi.mp3", include_bytes!("../sounds/yubiyubi.mp3"))
+];
+
+// The rom database, in the format of the community chip-8 database
+pub const ROM_PROGRAMS: &str = include_str!("../database/programs.json");
+pub const ROM_PLATFORMS: &str = include_str!("../database/platforms.json");
+
+pub fn sound_file(file: &str) -> Option<&'static [u8]> {
+    SOUNDS.iter().find(|(name, _)| *name == file).map(|(_, contents)| *contents)
+}
+
+pub fn theme_file(theme: &str, file: &str) -> Option<&'static [u8]> {
+    let (_, files) = THEMES.iter().find(|(name, _)| *name == theme)?;
+    files.iter().find(|(name, _)| *name == file).map(|(_, contents)| *contents)
+}
@@ -0,0 +1,139 @@
+use std::convert::TryFrom;
+use std::path::PathBuf;
+
+use clap::Parser;
+use crossterm::style::Color;
+
+use koro8::arch::Buzzer;
+use koro8::cheats::CheatFile;
+use koro8::config::Config;
+use koro8::constants::{DEFAULT_SPEED, MAX_SPEED};
+use koro8::framebuffer::Edge;
+use koro8::peripherals::{headless, terminal};
+use koro8::phosphor::{Persistence, Phosphor};
+
+/// koro8 in a terminal, for when there's no display around
+#[derive(Parser)]
+#[clap(version)]
+struct Args {
+    /// Path to the rom to run
+    #[clap(value_parser)]
+    rom: PathBuf,
+
+    /// Config file to use instead of the one in the user's config directory
+    #[clap(long, value_parser)]
+    config: Option<PathBuf>,
+
+    /// Cheat file to use instead of the one for the rom in the user's config directory
+    #[clap(long, value_parser)]
+    cheats: Option<PathBuf>,
+
+    /// Instructions executed per 60 Hz frame [default: 9]
+    #[clap(short, long, value_parser = clap::value_parser!(u64).range(1..=MAX_SPEED))]
+    speed: Option<u64>,
+
+    /// Speed multiplier while fast-forwarding, 0 for uncapped [default: 0]
+    #[clap(long, value_parser)]
+    fast_forward: Option<u64>,
+
+    /// Speed divisor in slow motion [default: 4]
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    slow_motion: Option<u64>,
+
+    /// What sprites drawn past the edge of the screen do: wrap or clip [default: wrap]
+    #[clap(long, value_parser)]
+    edge: Option<Edge>,
+
+    /// Draw with braille characters (8 pixels per character) instead of half blocks
+    #[clap(short, long)]
+    braille: bool,
+
+    /// Colour of lit pixels, e.g. white or dark_yellow
+    #[clap(long, value_parser = parse_color, default_value = "white")]
+    color: Color,
+
+    /// Colour of unlit pixels
+    #[clap(long, value_parser = parse_color, default_value = "black")]
+    background: Color,
+
+    /// How turned off pixels linger to reduce flicker: off, fade or max-of-two [default: off]
+    #[clap(long, value_parser)]
+    persistence: Option<Persistence>,
+
+    /// Frames a pixel takes to fade out with --persistence fade [default: 6]
+    #[clap(long, value_parser = clap::value_parser!(u8).range(1..))]
+    decay: Option<u8>,
+
+    /// Don't ring the terminal bell
+    #[clap(short, long)]
+    mute: bool
+}
+
+fn parse_color(arg: &str) -> Result<Color, String> {
+    Color::try_from(arg).map_err(|_| String::from("not a colour name like white, red or dark_grey"))
+}
+
+fn main() {
+    if let Err(err) = run(Args::parse()) {
+        eprintln!("koro8-term: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let rom = std::fs::read(&args.rom).map_err(|err| format!("can't read rom {}: {}", args.rom.display(), err))?;
+    let config = match &args.config {
+        Some(path) => Config::load(path),
+        None => Config::load_default()
+    }.map_err(|err| err.to_string())?;
+    let rom_hash = koro8::rom::hash(&rom);
+    let settings = config.settings_for(&rom_hash);
+    let cheats = match &args.cheats {
+        Some(path) => CheatFile::load(path),
+        None => CheatFile::load_default(&rom_hash)
+    }.map_err(|err| err.to_string())?.cheats;
+    let keymap = terminal::keyboard::Keyboard::keymap_from_names(&settings.keymap)?;
+    // The rom database's speed and edge win over the global ones, but not these
+    let overrides = config.rom_settings(&rom_hash);
+    let speed = args.speed.or(overrides.speed);
+    let edge = args.edge.or(overrides.edge);
+    if settings.speed == Some(0) {
+        return Err(String::from("speed must be at least 1"));
+    }
+
+    let term = terminal::Terminal::enter().map_err(|err| format!("can't set up the terminal: {}", err))?;
+    let glyphs = if args.braille { terminal::display::Glyphs::Braille } else { terminal::display::Glyphs::HalfBlock };
+    let phosphor = Phosphor::new(
+        args.persistence.or(settings.persistence).unwrap_or(Persistence::Off),
+        args.decay.or(settings.decay).unwrap_or(6)
+    );
+    let display = terminal::display::Display::new(glyphs, args.color, args.background, phosphor);
+    let keyboard = terminal::keyboard::Keyboard::new(keymap, term.reports_key_releases());
+    let buzzer: Box<dyn Buzzer> = if args.mute || settings.mute.unwrap_or(false) {
+        Box::new(headless::Buzzer)
+    } else {
+        Box::new(terminal::buzzer::Buzzer)
+    };
+    let cpu = koro8::cpu::Builder::new()
+        .display(display)
+        .keyboard(keyboard)
+        .buzzer(buzzer)
+        .edge(config.global.edge.unwrap_or(Edge::Wrap))
+        .cheats(cheats)
+        .speed(config.global.speed.unwrap_or(DEFAULT_SPEED))
+        .fast_forward_factor(args.fast_forward.or(settings.fast_forward).unwrap_or(0))
+        .slow_motion_factor(args.slow_motion.or(settings.slow_motion).unwrap_or(4))
+        .rom(&rom)
+        .build();
+    let loaded = cpu.map(|mut cpu| {
+        if let Some(speed) = speed {
+            cpu.set_clock_multiplier(speed);
+        }
+        if let Some(edge) = edge {
+            cpu.framebuffer_mut().set_edge(edge);
+        }
+        cpu.run()
+    });
+    drop(term);
+    loaded.map_err(|err| format!("can't load {}: {}", args.rom.display(), err))
+}
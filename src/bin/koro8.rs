@@ -1,37 +1,389 @@
-use sdl2::{image::LoadTexture};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, Subcommand};
+
+use koro8::arch::{Buzzer, Scaling, SoundMode, Waveform, SCALE};
+use koro8::cheats::{Cheat, CheatFile};
+use koro8::config::{Config, KeyBinding, RememberedSpeeds, Settings};
+use koro8::constants::{DEFAULT_SPEED, MAX_SPEED};
+use koro8::database::Database;
+use koro8::framebuffer::Edge;
+use koro8::peripherals::{headless, sdl};
+use koro8::phosphor::{Persistence, Phosphor};
+use koro8::soundpack::{Selection, SoundPack};
+use koro8::theme::{self, Theme};
+
+/// A CHIP-8 emulator with Korone-based sounds and visuals
+#[derive(Parser)]
+#[clap(version, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(flatten)]
+    run: RunArgs
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the size and SHA-1 hash of a rom, and what the rom database knows about it
+    Info {
+        /// Path to the rom
+        #[clap(value_parser)]
+        rom: PathBuf
+    }
+}
+
+#[derive(clap::Args)]
+struct RunArgs {
+    /// Path to the rom to run
+    #[clap(value_parser, required = true)]
+    rom: Option<PathBuf>,
+
+    /// Config file to use instead of the one in the user's config directory
+    #[clap(long, value_parser)]
+    config: Option<PathBuf>,
+
+    /// Cheat file to use instead of the one for the rom in the user's config directory
+    #[clap(long, value_parser)]
+    cheats: Option<PathBuf>,
+
+    /// Instructions executed per 60 Hz frame [default: 9]
+    #[clap(short, long, value_parser = clap::value_parser!(u64).range(1..=MAX_SPEED))]
+    speed: Option<u64>,
+
+    /// Speed multiplier while fast-forwarding, 0 for uncapped [default: 0]
+    #[clap(long, value_parser)]
+    fast_forward: Option<u64>,
+
+    /// Speed divisor in slow motion [default: 4]
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    slow_motion: Option<u64>,
+
+    /// What sprites drawn past the edge of the screen do: wrap or clip [default: wrap]
+    #[clap(long, value_parser)]
+    edge: Option<Edge>,
+
+    /// Size of a CHIP-8 pixel in the window as it opens [default: from the theme, or 16]
+    #[clap(long, value_parser = parse_scale)]
+    scale: Option<usize>,
+
+    /// How to fit the screen into the window: integer, nearest or smooth [default: integer]
+    #[clap(long, value_parser)]
+    scaling: Option<Scaling>,
+
+    /// Directory containing the sounds to play, optionally with a sounds.toml manifest [default: the built-in Korone sounds]
+    #[clap(long, value_parser)]
+    sound_dir: Option<PathBuf>,
+
+    /// Theme to start with: korone, monochrome, amber, lcd or a theme directory [default: korone]
+    #[clap(short, long, value_parser)]
+    theme: Option<String>,
+
+    /// How sounds follow the sound timer: tone, truncate or complete [default: complete]
+    #[clap(long, value_parser)]
+    sound_mode: Option<SoundMode>,
+
+    /// How sounds are picked: shuffle, round-robin or duration [default: from the sound pack]
+    #[clap(long, value_parser)]
+    sound_selection: Option<Selection>,
+
+    /// Beep with a synthesized square or sine tone instead of playing sounds
+    #[clap(long, value_parser)]
+    beep: Option<Waveform>,
+
+    /// Pitch of the beep in Hz [default: 440]
+    #[clap(long, value_parser = clap::value_parser!(u32).range(20..=20000))]
+    beep_frequency: Option<u32>,
+
+    /// Number of sounds that can play at the same time [default: 4]
+    #[clap(long, value_parser = clap::value_parser!(i32).range(1..))]
+    channels: Option<i32>,
+
+    /// Sound volume in percent [default: 100]
+    #[clap(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+    volume: Option<u8>,
+
+    /// Don't start a sound while this many are playing [default: as many as --channels]
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_playing: Option<u64>,
+
+    /// Start in borderless fullscreen mode
+    #[clap(short, long)]
+    fullscreen: bool,
+
+    /// Draw pixels as plain squares instead of pixel.png, which is faster at high speeds
+    #[clap(long)]
+    plain_pixels: bool,
+
+    /// How turned off pixels linger to reduce flicker: off, fade or max-of-two [default: off]
+    #[clap(long, value_parser)]
+    persistence: Option<Persistence>,
+
+    /// Frames a pixel takes to fade out with --persistence fade [default: 6]
+    #[clap(long, value_parser = clap::value_parser!(u8).range(1..))]
+    decay: Option<u8>,
+
+    /// Don't play any sounds
+    #[clap(short, long)]
+    mute: bool
+}
+
+impl RunArgs {
+    fn settings(&self) -> Settings {
+        Settings {
+            speed: self.speed,
+            fast_forward: self.fast_forward,
+            slow_motion: self.slow_motion,
+            edge: self.edge,
+            scale: self.scale,
+            scaling: self.scaling,
+            sound_dir: self.sound_dir.clone(),
+            theme: self.theme.clone(),
+            sound_mode: self.sound_mode,
+            sound_selection: self.sound_selection,
+            beep: self.beep,
+            beep_frequency: self.beep_frequency,
+            channels: self.channels,
+            volume: self.volume,
+            max_playing: self.max_playing.map(|max_playing| max_playing as usize),
+            fullscreen: Some(true).filter(|_| self.fullscreen),
+            plain_pixels: Some(true).filter(|_| self.plain_pixels),
+            persistence: self.persistence,
+            decay: self.decay,
+            mute: Some(true).filter(|_| self.mute),
+            keymap: HashMap::new(),
+            controller: HashMap::new()
+        }
+    }
+}
+
+const SCALES: std::ops::RangeInclusive<usize> = 1..=64;
+
+fn parse_scale(arg: &str) -> Result<usize, String> {
+    match arg.parse::<usize>() {
+        Ok(scale) if SCALES.contains(&scale) => Ok(scale),
+        _ => Err(format!("must be a number from {} to {}", SCALES.start(), SCALES.end()))
+    }
+}
 
 fn main() {
-    let args = std::env::args().skip(1).collect::<Vec<String>>();
-    let rom_path = args.first().expect("no arguments given");
-    let rom = std::fs::read(rom_path).unwrap_or_else(|_| panic!("no such file: {}", rom_path));
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Some(Command::Info { rom }) => info(&rom),
+        None => run(cli.run)
+    };
+    if let Err(err) = result {
+        eprintln!("koro8: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn read_rom(path: &Path) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|err| format!("can't read rom {}: {}", path.display(), err))
+}
 
-    let sdl = sdl2::init().unwrap();
-    let canvas = koro8::peripherals::sdl::display::Display::create_canvas(&sdl).unwrap();
+fn info(rom_path: &Path) -> Result<(), String> {
+    let rom = read_rom(rom_path)?;
+    println!("size: {} bytes", rom.len());
+    let rom_hash = koro8::rom::hash(&rom);
+    println!("sha1: {}", rom_hash);
+    if let Some(known) = Database::bundled().find(&rom_hash) {
+        println!("title: {}", known.title);
+        if !known.authors.is_empty() {
+            println!("authors: {}", known.authors.join(", "));
+        }
+        if let Some(platform) = &known.platform {
+            println!("platform: {}", platform);
+        }
+        if let Some(speed) = known.speed {
+            println!("speed: {} instructions/frame", speed);
+        }
+        if let Some(wrap) = known.quirks.wrap {
+            println!("edge: {}", if wrap { "wrap" } else { "clip" });
+        }
+        if !known.keys.is_empty() {
+            println!("keys: {}", known.key_hints());
+        }
+    }
+    Ok(())
+}
+
+fn load_config(args: &RunArgs) -> Result<Config, String> {
+    let config = match &args.config {
+        Some(path) => Config::load(path),
+        None => Config::load_default()
+    };
+    config.map_err(|err| err.to_string())
+}
+
+// The cheats for the rom, from the given file or the rom's cheat file if it has one.
+fn load_cheats(path: Option<&Path>, rom_hash: &str) -> Result<Vec<Cheat>, String> {
+    let cheats = match path {
+        Some(path) => CheatFile::load(path),
+        None => CheatFile::load_default(rom_hash)
+    };
+    cheats.map(|file| file.cheats).map_err(|err| err.to_string())
+}
+
+fn run(args: RunArgs) -> Result<(), String> {
+    let rom_path = args.rom.as_ref().expect("rom is a required argument");
+    let rom = read_rom(rom_path)?;
+    let config = load_config(&args)?;
+    let rom_hash = koro8::rom::hash(&rom);
+    let cheats = load_cheats(args.cheats.as_deref(), &rom_hash)?;
+    let mut speeds = load_remembered_speeds();
+    let remembered = Settings { speed: speeds.roms.get(&rom_hash).copied(), ..Settings::default() };
+    // The speed and edge the rom database recommends win over the global
+    // ones, which the CPU takes care of, but not over these
+    let overrides = config.rom_settings(&rom_hash).merge(&remembered).merge(&args.settings());
+    let settings = config.settings_for(&rom_hash).merge(&remembered).merge(&args.settings());
+    if settings.speed == Some(0) || settings.max_playing == Some(0) || matches!(settings.channels, Some(channels) if channels < 1) {
+        return Err(String::from("speed, channels and max_playing must be at least 1"));
+    }
+    let theme_name = settings.theme.clone().unwrap_or_else(|| String::from(theme::default_name()));
+    let mut themes = vec![Theme::find(&theme_name).map_err(|err| format!("can't load theme {}: {}", theme_name, err))?];
+    // The bundled themes to switch to at runtime
+    themes.extend(theme::bundled_names()
+        .filter(|name| *name != theme_name)
+        .filter_map(Theme::bundled));
+    let scale = settings.scale.or(themes[0].scale).unwrap_or(SCALE);
+    if !SCALES.contains(&scale) {
+        return Err(format!("scale must be a number from {} to {}", SCALES.start(), SCALES.end()));
+    }
+    if let Some(sound_dir) = settings.sound_dir.as_ref().filter(|dir| !dir.is_dir()) {
+        return Err(format!("sound directory {} doesn't exist", sound_dir.display()));
+    }
+
+    let sdl = sdl2::init()?;
+    let scaling = settings.scaling.unwrap_or(Scaling::Integer);
+    let canvas = sdl::display::Display::create_canvas(&sdl, scale, scaling, settings.fullscreen.unwrap_or(false))
+        .ok_or("can't open a window")?;
     let texture_creator = canvas.texture_creator();
-    let pixel_texture = texture_creator.load_texture("graphics/pixel.png").unwrap();
-    let background_texture = texture_creator.load_texture("graphics/background.png").unwrap();
-    let mut display = koro8::peripherals::sdl::display::Display::new(
+    let skins = themes.iter()
+        .map(|theme| sdl::display::Skin::load(&texture_creator, theme))
+        .collect::<Result<Vec<_>, _>>()?;
+    let framebuffer = sdl::display::Display::create_framebuffer(&texture_creator)
+        .ok_or("can't create the framebuffer texture")?;
+    let display = sdl::display::Display::new(
         canvas,
-        pixel_texture,
-        background_texture
-    ).unwrap();
+        skins,
+        framebuffer,
+        settings.plain_pixels.unwrap_or(false),
+        scaling,
+        Phosphor::new(settings.persistence.unwrap_or(Persistence::Off), settings.decay.unwrap_or(6))
+    ).ok_or("can't set up the display")?;
 
     let rng = rand::rngs::OsRng;
-    let keyboard = koro8::peripherals::sdl::keyboard::Keyboard::new(&sdl).unwrap();
-    let buzzer = koro8::peripherals::sdl::buzzer::Buzzer::new(
+    let keyboard = sdl::keyboard::Keyboard::new(
         &sdl,
-        "sounds",
-        4,
-        Box::new(rng)
-    ).unwrap();
-    let mut cpu = koro8::cpu::new(
-        &mut display,
-        Box::new(keyboard),
-        Box::new(buzzer),
-        Box::new(rng),
-        9
-    );
-    cpu.load(&rom);
+        sdl::keyboard::Keyboard::keymap_from_names(&settings.keymap)?,
+        sdl::keyboard::Keyboard::buttonmap_from_names(&settings.controller)?
+    ).ok_or("can't set up the keyboard")?;
+    let mut event_sounds = None;
+    let buzzer: Box<dyn Buzzer> = if settings.mute.unwrap_or(false) {
+        Box::new(headless::Buzzer)
+    } else if let Some(waveform) = settings.beep {
+        let beeper = sdl::beeper::Beeper::new(
+            &sdl,
+            waveform,
+            settings.beep_frequency.unwrap_or(440),
+            settings.volume.unwrap_or(100)
+        ).ok_or("can't beep (use --mute to run without sound)")?;
+        Box::new(beeper)
+    } else {
+        let mut pack = match &settings.sound_dir {
+            Some(sound_dir) => SoundPack::load(sound_dir).map_err(|err| err.to_string())?,
+            None => SoundPack::bundled().ok_or("can't load the built-in sounds")?
+        };
+        if let Some(selection) = settings.sound_selection {
+            pack.selection = selection;
+        }
+        let mut buzzer = sdl::buzzer::Buzzer::new(
+            &sdl,
+            pack,
+            settings.channels.unwrap_or(4),
+            settings.sound_mode.unwrap_or(SoundMode::Complete),
+            Box::new(rng)
+        ).ok_or_else(|| match &settings.sound_dir {
+            Some(sound_dir) => format!("can't play sounds from {} (use --mute to run without sound)", sound_dir.display()),
+            None => String::from("can't play sounds (use --mute to run without sound)")
+        })?;
+        buzzer.set_volume(settings.volume.unwrap_or(100));
+        buzzer.set_max_playing(settings.max_playing);
+        event_sounds = buzzer.event_sounds(Box::new(rng));
+        Box::new(buzzer)
+    };
+    let mut cpu = koro8::cpu::Builder::new()
+        .display(display)
+        .keyboard(keyboard)
+        .buzzer(buzzer)
+        .rng(rng)
+        .listener(event_sounds)
+        .edge(config.global.edge.unwrap_or(Edge::Wrap))
+        .cheats(cheats)
+        .speed(config.global.speed.unwrap_or(DEFAULT_SPEED))
+        .fast_forward_factor(settings.fast_forward.unwrap_or(0))
+        .slow_motion_factor(settings.slow_motion.unwrap_or(4))
+        .rom(&rom)
+        .build()
+        .map_err(|err| format!("can't load {}: {}", rom_path.display(), err))?;
+    if let Some(speed) = overrides.speed {
+        cpu.set_clock_multiplier(speed);
+    }
+    if let Some(edge) = overrides.edge {
+        cpu.framebuffer_mut().set_edge(edge);
+    }
+    if let Some(known) = cpu.rom_info().filter(|known| !known.keys.is_empty()) {
+        println!("{} keys: {}", known.title, known.key_hints());
+        let keys = known.keys.clone();
+        cpu.keyboard_mut().set_key_hints(&keys);
+    }
+    let speed = cpu.clock_multiplier();
     cpu.run();
+    if cpu.clock_multiplier() != speed {
+        speeds.roms.insert(rom_hash.clone(), cpu.clock_multiplier());
+        save_remembered_speeds(&speeds);
+    }
+    let remapped = cpu.keyboard().remapped_keys();
+    if !remapped.is_empty() {
+        save_keymap(args.config.as_deref(), &rom_hash, &remapped);
+    }
     drop(cpu);
+    Ok(())
+}
+
+// Keeps the keys changed on the remap screen in the config file it was loaded from.
+fn save_keymap(path: Option<&Path>, rom_hash: &str, keys: &HashMap<String, KeyBinding>) {
+    let path = match path.map(Path::to_path_buf).or_else(Config::default_path) {
+        Some(path) => path,
+        None => {
+            eprintln!("koro8: warning: nowhere to save the keymap");
+            return;
+        }
+    };
+    match Config::save_keymap(&path, rom_hash, keys) {
+        Ok(()) => println!("keymap saved to {}", path.display()),
+        Err(err) => eprintln!("koro8: warning: {}", err)
+    }
+}
+
+// Remembering speeds is a nicety, so problems with it are only warned about.
+fn load_remembered_speeds() -> RememberedSpeeds {
+    let speeds = RememberedSpeeds::default_path().map(|path| RememberedSpeeds::load(&path));
+    match speeds {
+        Some(Ok(speeds)) => speeds,
+        Some(Err(err)) => {
+            eprintln!("koro8: warning: {}", err);
+            RememberedSpeeds::default()
+        }
+        None => RememberedSpeeds::default()
+    }
+}
+
+fn save_remembered_speeds(speeds: &RememberedSpeeds) {
+    if let Some(Err(err)) = RememberedSpeeds::default_path().map(|path| speeds.save(&path)) {
+        eprintln!("koro8: warning: {}", err);
+    }
 }
@@ -0,0 +1,215 @@
+use std::{convert::TryFrom, path::{Path, PathBuf}};
+
+use serde::Deserialize;
+
+use crate::config::ConfigError;
+use crate::constants::{HEAP_SIZE, NUM_REGS};
+
+// What a cheat keeps at its value
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Target {
+    // A byte of memory, by address
+    Memory(u16),
+    // V0 to VF
+    Register(u8)
+}
+
+// Freezes a byte of memory or a register, by writing the value back every
+// frame, e.g. to keep the number of lives from going down.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(try_from = "Entry")]
+pub struct Cheat {
+    pub name: Option<String>,
+    pub target: Target,
+    pub value: u8,
+    pub enabled: bool
+}
+
+// A cheat as written in a cheat file, with either an address or a register.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    name: Option<String>,
+    address: Option<u16>,
+    register: Option<u8>,
+    value: u8,
+    #[serde(default = "Entry::default_enabled")]
+    enabled: bool
+}
+
+// The cheats for a rom, kept in a file named after its hash, see crate::rom::hash.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CheatFile {
+    pub cheats: Vec<Cheat>
+}
+
+// How a byte must compare to the last snapshot to stay a candidate, or to
+// a given value for Equal.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Compare {
+    Equal(u8),
+    Unchanged,
+    Changed,
+    Increased,
+    Decreased
+}
+
+// Finds where a rom keeps a value by narrowing down the addresses that behave
+// like it across snapshots of memory, e.g. the ones that decreased after
+// losing a life.
+pub struct Search {
+    snapshot: [u8; HEAP_SIZE],
+    candidates: Vec<u16>
+}
+
+impl Entry {
+    fn default_enabled() -> bool {
+        true
+    }
+}
+
+impl TryFrom<Entry> for Cheat {
+    type Error = String;
+
+    fn try_from(entry: Entry) -> Result<Cheat, String> {
+        let target = match (entry.address, entry.register) {
+            (Some(address), None) if (address as usize) < HEAP_SIZE => Target::Memory(address),
+            (Some(address), None) => return Err(format!("address {:#X} is past the end of memory", address)),
+            (None, Some(register)) if register < NUM_REGS => Target::Register(register),
+            (None, Some(register)) => return Err(format!("there's no register V{:X}", register)),
+            _ => return Err(String::from("a cheat needs either an address or a register"))
+        };
+        Ok(Cheat { name: entry.name, target, value: entry.value, enabled: entry.enabled })
+    }
+}
+
+impl CheatFile {
+    pub fn default_path(rom_hash: &str) -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("koro8").join("cheats").join(format!("{}.toml", rom_hash)))
+    }
+
+    pub fn load(path: &Path) -> Result<CheatFile, ConfigError> {
+        let contents = std::fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        toml::from_str(&contents).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))
+    }
+
+    // Loads the cheats for the rom from the default location, if there are any.
+    pub fn load_default(rom_hash: &str) -> Result<CheatFile, ConfigError> {
+        match CheatFile::default_path(rom_hash) {
+            Some(path) if path.exists() => CheatFile::load(&path),
+            _ => Ok(CheatFile::default())
+        }
+    }
+}
+
+impl Compare {
+    fn matches(self, old: u8, new: u8) -> bool {
+        match self {
+            Compare::Equal(value) => new == value,
+            Compare::Unchanged => new == old,
+            Compare::Changed => new != old,
+            Compare::Increased => new > old,
+            Compare::Decreased => new < old
+        }
+    }
+}
+
+impl Search {
+    // Every address is a candidate to begin with.
+    pub fn start(memory: &[u8; HEAP_SIZE]) -> Search {
+        Search { snapshot: *memory, candidates: (0..HEAP_SIZE as u16).collect() }
+    }
+
+    // Keeps the candidates whose byte in `memory` compares to the last snapshot
+    // as asked, then takes `memory` as the new snapshot.
+    pub fn narrow(&mut self, memory: &[u8; HEAP_SIZE], compare: Compare) {
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|addr| compare.matches(snapshot[*addr as usize], memory[*addr as usize]));
+        self.snapshot = *memory;
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNCHANGED: u16 = 0x10;
+    const INCREASED: u16 = 0x11;
+    const DECREASED: u16 = 0x12;
+    // Zero in both snapshots, like most of memory
+    const UNTOUCHED: u16 = 0x20;
+
+    fn snapshots() -> ([u8; HEAP_SIZE], [u8; HEAP_SIZE]) {
+        let mut before = [0; HEAP_SIZE];
+        let mut after = [0; HEAP_SIZE];
+        before[UNCHANGED as usize] = 5;
+        after[UNCHANGED as usize] = 5;
+        before[INCREASED as usize] = 5;
+        after[INCREASED as usize] = 7;
+        before[DECREASED as usize] = 5;
+        after[DECREASED as usize] = 3;
+        (before, after)
+    }
+
+    fn kept(search: &Search) -> Vec<u16> {
+        [UNCHANGED, INCREASED, DECREASED, UNTOUCHED].iter().copied()
+            .filter(|addr| search.candidates().contains(addr))
+            .collect()
+    }
+
+    #[test]
+    fn narrow_keeps_matching_addresses() {
+        let (before, after) = snapshots();
+        let expected = [
+            (Compare::Equal(7), vec![INCREASED]),
+            (Compare::Unchanged, vec![UNCHANGED, UNTOUCHED]),
+            (Compare::Changed, vec![INCREASED, DECREASED]),
+            (Compare::Increased, vec![INCREASED]),
+            (Compare::Decreased, vec![DECREASED])
+        ];
+        for (compare, expected) in expected.iter() {
+            let mut search = Search::start(&before);
+            search.narrow(&after, *compare);
+            assert_eq!(kept(&search), *expected, "{:?}", compare);
+        }
+    }
+
+    #[test]
+    fn narrow_compares_to_the_latest_snapshot() {
+        let (before, after) = snapshots();
+        let mut search = Search::start(&before);
+        search.narrow(&after, Compare::Changed);
+        // Against `after` now, so nothing changed a second time
+        search.narrow(&after, Compare::Unchanged);
+        assert_eq!(kept(&search), vec![INCREASED, DECREASED]);
+        search.narrow(&before, Compare::Increased);
+        assert_eq!(kept(&search), vec![DECREASED]);
+        assert_eq!(search.candidates(), &[DECREASED]);
+    }
+
+    fn parse(entry: &str) -> Result<CheatFile, toml::de::Error> {
+        toml::from_str(&format!("[[cheats]]\n{}\n", entry))
+    }
+
+    #[test]
+    fn entries_need_one_valid_target() {
+        let cheats = parse("name = \"lives\"\naddress = 0x3A0\nvalue = 9").unwrap().cheats;
+        assert_eq!(cheats[0].target, Target::Memory(0x3A0));
+        assert_eq!(cheats[0].name.as_deref(), Some("lives"));
+        assert!(cheats[0].enabled);
+        let cheats = parse("register = 15\nvalue = 1\nenabled = false").unwrap().cheats;
+        assert_eq!(cheats[0].target, Target::Register(0xF));
+        assert!(!cheats[0].enabled);
+
+        assert!(parse("address = 0x3A0\nregister = 1\nvalue = 9").is_err());
+        assert!(parse("value = 9").is_err());
+        assert!(parse("address = 0x1000\nvalue = 9").is_err());
+        assert!(parse("register = 16\nvalue = 9").is_err());
+        assert!(parse("address = 0x3A0\nvalue = 256").is_err());
+    }
+}
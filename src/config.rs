@@ -0,0 +1,363 @@
+use std::{collections::HashMap, fmt, io, path::{Path, PathBuf}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::arch::{Scaling, SoundMode, Waveform};
+use crate::framebuffer::Edge;
+use crate::phosphor::Persistence;
+use crate::soundpack::Selection;
+
+// Settings that can be given globally or per rom. Unset values fall through
+// to the next layer: command line, then the speed remembered for the rom,
+// then rom section, then the rom database, then global section.
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub speed: Option<u64>,
+    // Speed multiplier while fast-forwarding, 0 for uncapped
+    pub fast_forward: Option<u64>,
+    // Speed divisor in slow motion
+    pub slow_motion: Option<u64>,
+    // What sprites drawn past the edge of the screen do
+    pub edge: Option<Edge>,
+    pub scale: Option<usize>,
+    pub scaling: Option<Scaling>,
+    pub sound_dir: Option<PathBuf>,
+    // Bundled theme name or theme directory
+    pub theme: Option<String>,
+    pub sound_mode: Option<SoundMode>,
+    // Overrides the selection of the sound pack
+    pub sound_selection: Option<Selection>,
+    // Beep with a synthesized tone instead of playing sounds
+    pub beep: Option<Waveform>,
+    pub beep_frequency: Option<u32>,
+    pub channels: Option<i32>,
+    pub volume: Option<u8>,
+    // Sounds that may play at once, up to the number of channels
+    pub max_playing: Option<usize>,
+    pub fullscreen: Option<bool>,
+    pub plain_pixels: Option<bool>,
+    pub persistence: Option<Persistence>,
+    // Frames a pixel takes to fade out with fade persistence
+    pub decay: Option<u8>,
+    pub mute: Option<bool>,
+    // CHIP-8 key ("0" to "F") to host key names
+    pub keymap: HashMap<String, KeyBinding>,
+    // CHIP-8 key or "reset" to game controller button names
+    pub controller: HashMap<String, KeyBinding>
+}
+
+// One or more host key names bound to a CHIP-8 key, e.g. `5 = "Space"`
+// or `5 = ["Space", "Return"]`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum KeyBinding {
+    One(String),
+    Many(Vec<String>)
+}
+
+// The top level holds the global settings next to the rom sections. It isn't
+// deserialized with a flattened Settings, as serde can't reject unknown keys then.
+#[derive(Default, Debug)]
+pub struct Config {
+    pub global: Settings,
+    // Overrides keyed by the rom hash, see crate::rom::hash
+    pub roms: HashMap<String, Settings>
+}
+
+// Speeds chosen at runtime, kept separately from the config file so that
+// saving them doesn't mess with the user's formatting and comments.
+#[derive(Deserialize, Serialize, Default, Debug)]
+#[serde(default)]
+pub struct RememberedSpeeds {
+    // Instructions per frame keyed by rom hash
+    pub roms: HashMap<String, u64>
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Serialize(PathBuf, toml::ser::Error),
+    Invalid(PathBuf, String)
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "can't access {}: {}", path.display(), err),
+            ConfigError::Parse(path, err) => write!(f, "invalid config {}: {}", path.display(), err),
+            ConfigError::Serialize(path, err) => write!(f, "can't write {}: {}", path.display(), err),
+            ConfigError::Invalid(path, reason) => write!(f, "invalid config {}: {}", path.display(), reason)
+        }
+    }
+}
+
+impl std::error::Error for ConfigError { }
+
+impl KeyBinding {
+    pub fn names(&self) -> &[String] {
+        match self {
+            KeyBinding::One(name) => std::slice::from_ref(name),
+            KeyBinding::Many(names) => names
+        }
+    }
+}
+
+// A CHIP-8 key as named in a keymap, "0" to "F"
+fn chip8_key(name: &str) -> Option<usize> {
+    usize::from_str_radix(name.trim().trim_matches('"'), 16).ok().filter(|key| *key < 16)
+}
+
+// The table header on a line, without quotes and spaces, e.g. "[roms.abc.keymap]"
+fn section_header(line: &str) -> Option<String> {
+    let line = line.split('#').next().unwrap_or("").trim();
+    if line.starts_with('[') {
+        Some(line.chars().filter(|c| *c != '"' && !c.is_whitespace()).collect())
+    } else {
+        None
+    }
+}
+
+// The CHIP-8 key a keymap line binds, if it's one of those
+fn entry_key(line: &str) -> Option<usize> {
+    let (key, _) = line.split_once('=')?;
+    chip8_key(key)
+}
+
+impl Settings {
+    // Returns these settings with every value that is set in `other` replaced.
+    pub fn merge(mut self, other: &Settings) -> Settings {
+        self.speed = other.speed.or(self.speed);
+        self.fast_forward = other.fast_forward.or(self.fast_forward);
+        self.slow_motion = other.slow_motion.or(self.slow_motion);
+        self.edge = other.edge.or(self.edge);
+        self.scale = other.scale.or(self.scale);
+        self.scaling = other.scaling.or(self.scaling);
+        self.sound_dir = other.sound_dir.clone().or(self.sound_dir);
+        self.theme = other.theme.clone().or(self.theme);
+        self.sound_mode = other.sound_mode.or(self.sound_mode);
+        self.sound_selection = other.sound_selection.or(self.sound_selection);
+        self.beep = other.beep.or(self.beep);
+        self.beep_frequency = other.beep_frequency.or(self.beep_frequency);
+        self.channels = other.channels.or(self.channels);
+        self.volume = other.volume.or(self.volume);
+        self.max_playing = other.max_playing.or(self.max_playing);
+        self.fullscreen = other.fullscreen.or(self.fullscreen);
+        self.plain_pixels = other.plain_pixels.or(self.plain_pixels);
+        self.persistence = other.persistence.or(self.persistence);
+        self.decay = other.decay.or(self.decay);
+        self.mute = other.mute.or(self.mute);
+        self.keymap.extend(other.keymap.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self.controller.extend(other.controller.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self
+    }
+}
+
+impl Config {
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("koro8").join("config.toml"))
+    }
+
+    pub fn parse(path: &Path, contents: &str) -> Result<Config, ConfigError> {
+        let parse_error = |err| ConfigError::Parse(path.to_path_buf(), err);
+        let mut table: toml::value::Table = toml::from_str(contents).map_err(parse_error)?;
+        let roms = match table.remove("roms") {
+            Some(roms) => roms.try_into().map_err(parse_error)?,
+            None => HashMap::new()
+        };
+        let global = toml::Value::Table(table).try_into().map_err(parse_error)?;
+        Ok(Config { global, roms })
+    }
+
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let contents = std::fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        Config::parse(path, &contents)
+    }
+
+    // Loads the config from the default location, if there is one.
+    pub fn load_default() -> Result<Config, ConfigError> {
+        match Config::default_path() {
+            Some(path) if path.exists() => Config::load(&path),
+            _ => Ok(Config::default())
+        }
+    }
+
+    // Writes keys changed at runtime into the config file, each into the rom's
+    // [roms.<hash>.keymap] section if that binds the key already, otherwise into
+    // [keymap]. The rest of the file is left as it is.
+    pub fn save_keymap(path: &Path, rom_hash: &str, keys: &HashMap<String, KeyBinding>) -> Result<(), ConfigError> {
+        let contents = if path.exists() {
+            std::fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))?
+        } else {
+            String::new()
+        };
+        let updated = Config::with_keymap(path, &contents, rom_hash, keys)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| ConfigError::Io(dir.to_path_buf(), err))?;
+        }
+        std::fs::write(path, updated).map_err(|err| ConfigError::Io(path.to_path_buf(), err))
+    }
+
+    // The contents of a config file with the keys bound as given, see save_keymap.
+    fn with_keymap(path: &Path, contents: &str, rom_hash: &str, keys: &HashMap<String, KeyBinding>) -> Result<String, ConfigError> {
+        let invalid = |reason: String| ConfigError::Invalid(path.to_path_buf(), reason);
+        let config = Config::parse(path, contents)?;
+        let rom_keymap = config.roms.get(rom_hash).map(|settings| &settings.keymap);
+        let mut keys: Vec<_> = keys.iter()
+            .map(|(key, binding)| Ok((chip8_key(key).ok_or_else(|| invalid(format!("invalid CHIP-8 key {}", key)))?, binding)))
+            .collect::<Result<_, ConfigError>>()?;
+        keys.sort_by_key(|(key, _)| *key);
+
+        let mut lines: Vec<String> = contents.lines().map(String::from).collect();
+        for (key, binding) in &keys {
+            let in_rom_section = rom_keymap.is_some_and(|keymap| keymap.keys().any(|bound| chip8_key(bound) == Some(*key)));
+            let (header, keymap) = if in_rom_section {
+                (format!("[roms.{}.keymap]", rom_hash), rom_keymap)
+            } else {
+                (String::from("[keymap]"), Some(&config.global.keymap))
+            };
+            let names: Vec<_> = binding.names().iter().map(|name| format!("{:?}", name)).collect();
+            let entry = format!("{:X} = [{}]", key, names.join(", "));
+            match lines.iter().position(|line| section_header(line).as_deref() == Some(header.as_str())) {
+                Some(start) => {
+                    let end = lines[start + 1..].iter()
+                        .position(|line| section_header(line).is_some())
+                        .map_or(lines.len(), |ix| start + 1 + ix);
+                    match (start + 1..end).find(|ix| entry_key(&lines[*ix]) == Some(*key)) {
+                        Some(ix) => lines[ix] = entry,
+                        None => {
+                            // After the section's last entry, before any blank lines
+                            let last = (start..end).rev().find(|ix| !lines[*ix].trim().is_empty()).unwrap_or(start);
+                            lines.insert(last + 1, entry);
+                        }
+                    }
+                }
+                // Bound some other way, e.g. as an inline table
+                None if keymap.is_some_and(|keymap| !keymap.is_empty()) => {
+                    return Err(invalid(format!("can't update the keymap, it isn't written as a {} section", header)));
+                }
+                None => {
+                    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+                        lines.pop();
+                    }
+                    if !lines.is_empty() {
+                        lines.push(String::new());
+                    }
+                    lines.push(header);
+                    lines.push(entry);
+                }
+            }
+        }
+        let mut updated = lines.join("\n");
+        updated.push('\n');
+
+        // Anything the edit got wrong, like a binding spanning lines, shows up here
+        let check = Config::parse(path, &updated).map_err(|err| invalid(format!("can't update the keymap: {}", err)))?;
+        let written = |keymap: &HashMap<String, KeyBinding>, key: usize| keymap.iter()
+            .find(|(bound, _)| chip8_key(bound) == Some(key))
+            .map(|(_, binding)| binding.names().to_vec());
+        for (key, binding) in &keys {
+            let in_rom = check.roms.get(rom_hash).and_then(|settings| written(&settings.keymap, *key));
+            let bound = in_rom.or_else(|| written(&check.global.keymap, *key));
+            if bound.as_deref() != Some(binding.names()) {
+                return Err(invalid(format!("can't update the keymap entry for {:X}", key)));
+            }
+        }
+        Ok(updated)
+    }
+
+    // The settings to use for the rom with the given hash, without what the
+    // rom database recommends, which the CPU applies when it loads the rom.
+    pub fn settings_for(&self, rom_hash: &str) -> Settings {
+        self.global.clone().merge(&self.rom_settings(rom_hash))
+    }
+
+    // Just the rom's own section, which wins over the rom database.
+    pub fn rom_settings(&self, rom_hash: &str) -> Settings {
+        self.roms.get(rom_hash).cloned().unwrap_or_default()
+    }
+}
+
+impl RememberedSpeeds {
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_local_dir().map(|dir| dir.join("koro8").join("speeds.toml"))
+    }
+
+    // Loads the remembered speeds, or none if there aren't any yet.
+    pub fn load(path: &Path) -> Result<RememberedSpeeds, ConfigError> {
+        if !path.exists() {
+            return Ok(RememberedSpeeds::default());
+        }
+        let contents = std::fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        toml::from_str(&contents).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        let contents = toml::to_string(self).map_err(|err| ConfigError::Serialize(path.to_path_buf(), err))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| ConfigError::Io(dir.to_path_buf(), err))?;
+        }
+        std::fs::write(path, contents).map_err(|err| ConfigError::Io(path.to_path_buf(), err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    fn keys(entries: &[(&str, &[&str])]) -> HashMap<String, KeyBinding> {
+        entries.iter()
+            .map(|(key, names)| (key.to_string(), KeyBinding::Many(names.iter().map(|name| name.to_string()).collect())))
+            .collect()
+    }
+
+    fn with_keymap(contents: &str, entries: &[(&str, &[&str])]) -> Result<String, ConfigError> {
+        Config::with_keymap(Path::new("config.toml"), contents, HASH, &keys(entries))
+    }
+
+    #[test]
+    fn keymap_section_in_the_middle() {
+        let contents = "speed = 12 # fast\n\n[keymap] # mine\n1 = \"X\"\n5 = [\"Space\"]\n\n[roms.abc]\nspeed = 3\n";
+        let updated = with_keymap(contents, &[("5", &["Up", "W"]), ("a", &["Q"])]).unwrap();
+        assert_eq!(updated, "speed = 12 # fast\n\n[keymap] # mine\n1 = \"X\"\n5 = [\"Up\", \"W\"]\nA = [\"Q\"]\n\n[roms.abc]\nspeed = 3\n");
+    }
+
+    #[test]
+    fn keymap_section_at_the_end() {
+        let contents = "[roms.abc]\nspeed = 3\n\n[keymap]\n1 = \"X\"\n";
+        let updated = with_keymap(contents, &[("1", &["Y"]), ("2", &["Z"])]).unwrap();
+        assert_eq!(updated, "[roms.abc]\nspeed = 3\n\n[keymap]\n1 = [\"Y\"]\n2 = [\"Z\"]\n");
+    }
+
+    #[test]
+    fn keymap_section_missing() {
+        let updated = with_keymap("speed = 12\n\n[roms.abc]\nspeed = 3\n\n", &[("F", &["Space"])]).unwrap();
+        assert_eq!(updated, "speed = 12\n\n[roms.abc]\nspeed = 3\n\n[keymap]\nF = [\"Space\"]\n");
+    }
+
+    #[test]
+    fn keymap_in_empty_file() {
+        assert_eq!(with_keymap("", &[("5", &["Space"])]).unwrap(), "[keymap]\n5 = [\"Space\"]\n");
+    }
+
+    #[test]
+    fn inline_keymap_is_refused() {
+        assert!(matches!(with_keymap("keymap = { 1 = \"X\" }\n", &[("5", &["Space"])]), Err(ConfigError::Invalid(_, _))));
+    }
+
+    #[test]
+    fn keys_the_rom_binds_stay_in_its_section() {
+        let contents = format!("[keymap]\n1 = \"X\"\n\n[roms.{0}]\nspeed = 3\n\n[roms.{0}.keymap]\n5 = \"Space\"\n", HASH);
+        let updated = with_keymap(&contents, &[("5", &["Up"]), ("6", &["Down"])]).unwrap();
+        assert_eq!(updated, format!("[keymap]\n1 = \"X\"\n6 = [\"Down\"]\n\n[roms.{0}]\nspeed = 3\n\n[roms.{0}.keymap]\n5 = [\"Up\"]\n", HASH));
+        let config = Config::parse(Path::new("config.toml"), &updated).unwrap();
+        let settings = config.settings_for(HASH);
+        assert_eq!(settings.keymap["5"].names(), ["Up"]);
+        assert_eq!(settings.keymap["6"].names(), ["Down"]);
+        // Other roms don't get this one's keys
+        assert!(!config.settings_for("another").keymap.contains_key("5"));
+    }
+}
@@ -4,3 +4,8 @@ pub const HEAP_SIZE: usize = 4096;
 pub const STACK_SIZE: usize = 16;
 pub const RESET_VECTOR: u16 = 512;
 pub const TIMER_HZ: u64 = 60;
+// Instructions per frame unless told otherwise
+pub const DEFAULT_SPEED: u64 = 9;
+// Instructions per frame, far beyond what any rom needs, and small enough to keep the timing math from overflowing
+pub const MAX_SPEED: u64 = 1_000_000;
+pub const MAX_ROM_SIZE: usize = HEAP_SIZE - RESET_VECTOR as usize;
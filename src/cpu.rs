@@ -1,13 +1,25 @@
-mod font;
+pub mod font;
 mod mem;
+mod op;
 
-use std::thread;
+use std::{fmt, thread};
 use std::time::{SystemTime, UNIX_EPOCH, Duration};
-use rand::Rng;
-use crate::arch::{Display, Keyboard, Buzzer};
-use crate::constants::{NUM_REGS, RESET_VECTOR, TIMER_HZ, LAST_REG};
+use rand::{Rng, RngCore, rngs::OsRng};
+use crate::arch::{Display, Keyboard, Buzzer, Change, Event, Listener, Sprite, RunState};
+use crate::cheats::{Cheat, Target};
+use crate::database::{Database, RomInfo};
+use crate::framebuffer::{Edge, Framebuffer};
+use crate::peripherals::headless;
+use crate::constants::{NUM_REGS, RESET_VECTOR, TIMER_HZ, DEFAULT_SPEED, MAX_SPEED, LAST_REG, MAX_ROM_SIZE, HEAP_SIZE, STACK_SIZE};
 
-use self::mem::Instr;
+use self::op::Op;
+
+// How far execution may fall behind real time before we stop trying to catch up,
+// e.g. after a long fast-forward or while the host was busy.
+const MAX_LAG_NANOS: u64 = 100_000_000;
+const FRAME_NANOS: u64 = 1_000_000_000 / TIMER_HZ;
+// Change in percent for each press of a volume hotkey
+const VOLUME_STEP: u8 = 10;
 
 struct Regs {
     v: [u8; NUM_REGS as usize],
@@ -29,54 +41,260 @@ impl Regs {
     }
 }
 
-pub struct CPU<'t> {
-    display: &'t mut dyn Display,
-    keyboard: Box<dyn Keyboard>,
-    buzzer: Box<dyn Buzzer>,
-    rng: Box<dyn rand::RngCore>,
+#[derive(Debug)]
+pub enum LoadError {
+    RomTooLarge { size: usize, max: usize }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::RomTooLarge { size, max } => write!(f, "rom is {} bytes, but at most {} bytes fit in memory", size, max)
+        }
+    }
+}
+
+impl std::error::Error for LoadError { }
+
+// An emulated CHIP-8 with its peripherals, made with a Builder. It owns
+// everything it uses, so it can be sent to another thread if the peripherals can.
+// Peripheral calls are resolved at compile time, see CPU for a type-erased one.
+pub struct Cpu<D, K, B, R, L = ()> {
+    display: D,
+    keyboard: K,
+    buzzer: B,
+    rng: R,
+    // Notified of events, if anyone cares
+    listener: L,
+    framebuffer: Framebuffer,
+    // Applied once per frame
+    cheats: Vec<Cheat>,
     clock_multiplier: u64,
     cycle_time_nanos: u64,
     cycle_sleep_millis: u64,
+    // 0 means as fast as possible
+    fast_forward_factor: u64,
+    slow_motion_factor: u64,
 
     next_cycle_deadline: u64,
+    next_poll_deadline: u64,
+    next_present_deadline: u64,
     cycles: u64,
+    paused: bool,
+    slow_motion: bool,
+    fast_forward: bool,
+    run_state: RunState,
 
     regs: Regs,
     heap: mem::Heap,
     stack: mem::Stack,
-    rom: &'t[u8]
+    rom: Vec<u8>,
+    // From the rom database, if the rom is in it
+    rom_info: Option<RomInfo>,
+    // Whether loading a known rom applies its recommended speed and edge
+    rom_database: bool
+}
+
+// A CPU with peripherals picked at runtime, see Builder::erase
+pub type CPU<'p> = Cpu<Box<dyn Display + 'p>, Box<dyn Keyboard + 'p>, Box<dyn Buzzer + 'p>, Box<dyn RngCore + 'p>, Box<dyn Listener + 'p>>;
+
+pub type ErasedBuilder<'p> = Builder<Box<dyn Display + 'p>, Box<dyn Keyboard + 'p>, Box<dyn Buzzer + 'p>, Box<dyn RngCore + 'p>, Box<dyn Listener + 'p>>;
+
+// Puts a CPU together. Peripherals not given are headless, see crate::peripherals::headless.
+pub struct Builder<D, K, B, R, L> {
+    display: D,
+    keyboard: K,
+    buzzer: B,
+    rng: R,
+    listener: L,
+    options: Options
+}
+
+// Everything but the peripherals, carried over as they change type
+struct Options {
+    edge: Edge,
+    cheats: Vec<Cheat>,
+    clock_multiplier: u64,
+    fast_forward_factor: u64,
+    slow_motion_factor: u64,
+    rom_database: bool,
+    rom: Vec<u8>
+}
+
+impl Builder<headless::Display, headless::Keyboard, headless::Buzzer, OsRng, ()> {
+    pub fn new() -> Self {
+        Builder {
+            display: headless::Display,
+            keyboard: headless::Keyboard::new(),
+            buzzer: headless::Buzzer,
+            rng: OsRng,
+            listener: (),
+            options: Options {
+                edge: Edge::Wrap,
+                cheats: Vec::new(),
+                clock_multiplier: DEFAULT_SPEED,
+                fast_forward_factor: 0,
+                slow_motion_factor: 4,
+                rom_database: true,
+                rom: Vec::new()
+            }
+        }
+    }
+}
+
+impl Default for Builder<headless::Display, headless::Keyboard, headless::Buzzer, OsRng, ()> {
+    fn default() -> Self {
+        Builder::new()
+    }
+}
+
+impl <D, K, B, R, L> Builder<D, K, B, R, L> {
+    pub fn display<T: Display>(self, display: T) -> Builder<T, K, B, R, L> {
+        let Builder { keyboard, buzzer, rng, listener, options, .. } = self;
+        Builder { display, keyboard, buzzer, rng, listener, options }
+    }
+
+    pub fn keyboard<T: Keyboard>(self, keyboard: T) -> Builder<D, T, B, R, L> {
+        let Builder { display, buzzer, rng, listener, options, .. } = self;
+        Builder { display, keyboard, buzzer, rng, listener, options }
+    }
+
+    pub fn buzzer<T: Buzzer>(self, buzzer: T) -> Builder<D, K, T, R, L> {
+        let Builder { display, keyboard, rng, listener, options, .. } = self;
+        Builder { display, keyboard, buzzer, rng, listener, options }
+    }
+
+    // Source of CXNN's random numbers, seeded for reproducible runs
+    pub fn rng<T: RngCore>(self, rng: T) -> Builder<D, K, B, T, L> {
+        let Builder { display, keyboard, buzzer, listener, options, .. } = self;
+        Builder { display, keyboard, buzzer, rng, listener, options }
+    }
+
+    pub fn listener<T: Listener>(self, listener: T) -> Builder<D, K, B, R, T> {
+        let Builder { display, keyboard, buzzer, rng, options, .. } = self;
+        Builder { display, keyboard, buzzer, rng, listener, options }
+    }
+
+    // Boxes the peripherals, so that CPUs made of different ones have the same type.
+    pub fn erase<'p>(self) -> ErasedBuilder<'p>
+    where D: Display + 'p, K: Keyboard + 'p, B: Buzzer + 'p, R: RngCore + 'p, L: Listener + 'p {
+        Builder {
+            display: Box::new(self.display),
+            keyboard: Box::new(self.keyboard),
+            buzzer: Box::new(self.buzzer),
+            rng: Box::new(self.rng),
+            listener: Box::new(self.listener),
+            options: self.options
+        }
+    }
+
+    // What sprites drawn past the edge of the screen do
+    pub fn edge(mut self, edge: Edge) -> Self {
+        self.options.edge = edge;
+        self
+    }
+
+    pub fn cheats(mut self, cheats: Vec<Cheat>) -> Self {
+        self.options.cheats = cheats;
+        self
+    }
+
+    // Instructions executed per 60 Hz frame, 9 unless given. The speed and
+    // edge are for roms the database doesn't know, see rom_database.
+    pub fn speed(mut self, clock_multiplier: u64) -> Self {
+        self.options.clock_multiplier = clock_multiplier;
+        self
+    }
+
+    pub fn fast_forward_factor(mut self, factor: u64) -> Self {
+        self.options.fast_forward_factor = factor;
+        self
+    }
+
+    pub fn slow_motion_factor(mut self, factor: u64) -> Self {
+        self.options.slow_motion_factor = factor;
+        self
+    }
+
+    // Whether loading a rom the database knows applies its recommended speed
+    // and sprite edge behaviour, see crate::database. On by default.
+    pub fn rom_database(mut self, enabled: bool) -> Self {
+        self.options.rom_database = enabled;
+        self
+    }
+
+    // The rom is copied, so it needn't outlive the CPU.
+    pub fn rom(mut self, rom: &[u8]) -> Self {
+        self.options.rom = rom.to_vec();
+        self
+    }
 }
 
-pub fn new(
-    display: &mut dyn Display,
-    keyboard: Box<dyn Keyboard>,
-    buzzer: Box<dyn Buzzer>,
-    rng: Box<dyn rand::RngCore>,
-    clock_multiplier: u64
-) -> CPU {
-    let cycles_per_second = clock_multiplier * TIMER_HZ;
-    let cycle_time_nanos = 1_000_000_000 / cycles_per_second;
-    CPU {
-        display,
-        keyboard,
-        buzzer,
-        rng,
-        clock_multiplier,
-        cycle_time_nanos,
-        cycle_sleep_millis: std::cmp::max(1, cycle_time_nanos / 1_000_000),
-        next_cycle_deadline: 0,
-        regs: Regs::new(),
-        cycles: 0,
-        heap: mem::Heap::new(),
-        stack: mem::Stack::new(),
-        rom: &[0;0],
+impl <D: Display, K: Keyboard, B: Buzzer, R: RngCore, L: Listener> Builder<D, K, B, R, L> {
+    pub fn build(self) -> Result<Cpu<D, K, B, R, L>, LoadError> {
+        let options = self.options;
+        let mut cpu = Cpu {
+            display: self.display,
+            keyboard: self.keyboard,
+            buzzer: self.buzzer,
+            rng: self.rng,
+            listener: self.listener,
+            framebuffer: Framebuffer::new(options.edge),
+            cheats: options.cheats,
+            clock_multiplier: 1,
+            cycle_time_nanos: 0,
+            cycle_sleep_millis: 0,
+            fast_forward_factor: 0,
+            slow_motion_factor: 4,
+            next_cycle_deadline: 0,
+            next_poll_deadline: 0,
+            next_present_deadline: 0,
+            regs: Regs::new(),
+            cycles: 0,
+            paused: false,
+            slow_motion: false,
+            fast_forward: false,
+            run_state: RunState::Running,
+            heap: mem::Heap::new(),
+            stack: mem::Stack::new(),
+            rom: Vec::new(),
+            rom_info: None,
+            rom_database: options.rom_database
+        };
+        cpu.set_clock_multiplier(options.clock_multiplier);
+        cpu.set_fast_forward_factor(options.fast_forward_factor);
+        cpu.set_slow_motion_factor(options.slow_motion_factor);
+        cpu.load(&options.rom)?;
+        Ok(cpu)
     }
 }
 
-impl <'t> CPU<'t> {
-    pub fn load(&mut self, rom: &'t[u8]) {
-        self.rom = rom;
-        self.reset()
+impl <D: Display, K: Keyboard, B: Buzzer, R: RngCore, L: Listener> Cpu<D, K, B, R, L> {
+    pub fn load(&mut self, rom: &[u8]) -> Result<(), LoadError> {
+        if rom.len() > MAX_ROM_SIZE {
+            return Err(LoadError::RomTooLarge { size: rom.len(), max: MAX_ROM_SIZE });
+        }
+        self.rom = rom.to_vec();
+        self.rom_info = Database::bundled().find(&crate::rom::hash(rom)).cloned();
+        self.display.show_title(self.rom_info.as_ref().map(|info| info.title.as_str()));
+        let key_hints = self.rom_info.as_ref().filter(|info| !info.keys.is_empty()).map(RomInfo::key_hints);
+        self.display.show_key_hints(key_hints.as_deref());
+        if let Some(recommended) = self.rom_info.as_ref().filter(|_| self.rom_database).map(RomInfo::settings) {
+            if let Some(speed) = recommended.speed {
+                self.set_clock_multiplier(speed);
+            }
+            if let Some(edge) = recommended.edge {
+                self.framebuffer.set_edge(edge);
+            }
+        }
+        self.reset();
+        Ok(())
+    }
+
+    // What the rom database knows about the loaded rom, e.g. its title and
+    // what its keys do.
+    pub fn rom_info(&self) -> Option<&RomInfo> {
+        self.rom_info.as_ref()
     }
 
     pub fn reset(&mut self) {
@@ -84,180 +302,514 @@ impl <'t> CPU<'t> {
         self.cycles = 0;
         self.heap.reset();
         self.stack.reset();
+        self.framebuffer.clear();
         self.display.reset();
         self.keyboard.reset();
         self.buzzer.reset();
         self.heap.write_bytes(0, &font::FONT_DATA);
-        self.heap.write_bytes(RESET_VECTOR, self.rom);
+        self.heap.write_bytes(RESET_VECTOR, &self.rom);
+        self.emit(Event::Reset);
+    }
+
+    pub fn display(&self) -> &D {
+        &self.display
+    }
+
+    pub fn display_mut(&mut self) -> &mut D {
+        &mut self.display
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    pub fn framebuffer_mut(&mut self) -> &mut Framebuffer {
+        &mut self.framebuffer
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    // For adding, removing and toggling cheats while running
+    pub fn cheats_mut(&mut self) -> &mut Vec<Cheat> {
+        &mut self.cheats
+    }
+
+    pub fn keyboard(&self) -> &K {
+        &self.keyboard
+    }
+
+    pub fn keyboard_mut(&mut self) -> &mut K {
+        &mut self.keyboard
+    }
+
+    pub fn buzzer(&self) -> &B {
+        &self.buzzer
+    }
+
+    pub fn buzzer_mut(&mut self) -> &mut B {
+        &mut self.buzzer
+    }
+
+    pub fn listener_mut(&mut self) -> &mut L {
+        &mut self.listener
+    }
+
+    // Memory, registers and timers, for debuggers, trainers and tests. Changes
+    // made through these are passed to the listener like the rom's own.
+
+    pub fn memory(&self) -> &[u8; HEAP_SIZE] {
+        self.heap.bytes()
+    }
+
+    // Addresses wrap around at the end of memory.
+    pub fn read_memory(&self, addr: u16, dst: &mut [u8]) {
+        self.heap.read_bytes(addr, dst);
+    }
+
+    pub fn write_memory(&mut self, addr: u16, src: &[u8]) {
+        self.heap.write_bytes(addr, src);
+        self.listener.changed(Change::Memory { addr, len: src.len() });
+    }
+
+    pub fn registers(&self) -> &[u8; NUM_REGS as usize] {
+        &self.regs.v
+    }
+
+    // Only the low nibble of `x` counts, like in an instruction.
+    pub fn register(&self, x: u8) -> u8 {
+        self.regs.v[(x & 0xF) as usize]
+    }
+
+    pub fn set_register(&mut self, x: u8, val: u8) {
+        self.regs.v[(x & 0xF) as usize] = val;
+        self.listener.changed(Change::Register(x & 0xF));
+    }
+
+    pub fn i(&self) -> u16 {
+        self.regs.i
+    }
+
+    pub fn set_i(&mut self, i: u16) {
+        self.regs.i = i;
+        self.listener.changed(Change::I);
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.regs.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.regs.pc = pc;
+        self.listener.changed(Change::Pc);
+    }
+
+    pub fn stack(&self) -> &[u16; STACK_SIZE] {
+        self.stack.entries()
+    }
+
+    // Indices wrap around like the stack pointer.
+    pub fn set_stack_entry(&mut self, ix: usize, val: u16) {
+        self.stack.set_entry(ix, val);
+        self.listener.changed(Change::Stack);
+    }
+
+    // Index of the next free stack entry
+    pub fn sp(&self) -> usize {
+        self.stack.sp()
+    }
+
+    pub fn set_sp(&mut self, sp: usize) {
+        self.stack.set_sp(sp);
+        self.listener.changed(Change::Stack);
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.regs.dt
+    }
+
+    pub fn set_delay_timer(&mut self, val: u8) {
+        self.regs.dt = val;
+        self.listener.changed(Change::DelayTimer);
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.regs.st
+    }
+
+    // Starts or stops the buzzer like FX18 does.
+    pub fn set_sound_timer(&mut self, val: u8) {
+        if self.regs.st == 0 && val > 0 {
+            self.buzzer.start(val);
+        } else if self.regs.st > 0 && val == 0 {
+            self.buzzer.stop();
+        }
+        self.regs.st = val;
+        self.listener.changed(Change::SoundTimer);
+    }
+
+    // Writes back the values of enabled cheats that the rom changed.
+    fn apply_cheats(&mut self) {
+        for ix in 0..self.cheats.len() {
+            let (target, value) = match &self.cheats[ix] {
+                cheat if cheat.enabled => (cheat.target, cheat.value),
+                _ => continue
+            };
+            match target {
+                Target::Memory(addr) if self.heap.read_byte(addr) != value => self.write_memory(addr, &[value]),
+                Target::Register(x) if self.register(x) != value => self.set_register(x, value),
+                _ => { }
+            }
+        }
+    }
+
+    fn emit(&mut self, event: Event) {
+        self.listener.notify(event);
+    }
+
+    // Sets the number of instructions executed per 60 Hz frame.
+    pub fn set_clock_multiplier(&mut self, clock_multiplier: u64) {
+        self.clock_multiplier = clock_multiplier.clamp(1, MAX_SPEED);
+        self.cycle_time_nanos = 1_000_000_000 / (self.clock_multiplier * TIMER_HZ);
+        self.cycle_sleep_millis = std::cmp::max(1, self.cycle_time_nanos / 1_000_000);
+        self.display.show_speed(self.clock_multiplier);
+    }
+
+    pub fn clock_multiplier(&self) -> u64 {
+        self.clock_multiplier
+    }
+
+    // Speed while fast-forwarding, as a multiple of normal speed. 0 runs uncapped.
+    pub fn set_fast_forward_factor(&mut self, factor: u64) {
+        self.fast_forward_factor = factor;
+    }
+
+    // Speed in slow motion, as a fraction of normal speed.
+    pub fn set_slow_motion_factor(&mut self, factor: u64) {
+        self.slow_motion_factor = std::cmp::max(1, factor);
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.update_run_state();
+    }
+
+    pub fn run_state(&self) -> RunState {
+        self.run_state
     }
 
     pub fn run(&mut self) {
-        self.next_cycle_deadline = CPU::now();
-        self.execute(u64::max_value())
+        self.next_cycle_deadline = Self::now();
+        self.execute(u64::MAX)
     }
 
     fn execute(&mut self, mut ticks: u64) {
         while ticks > 0 {
-            let now = CPU::now();
-            if now >= self.next_cycle_deadline {
+            let now = Self::now();
+            // Drawing only updates the framebuffer, showing it happens once per frame
+            if now >= self.next_present_deadline {
+                self.next_present_deadline = now + FRAME_NANOS;
+                self.display.present(self.framebuffer.pixels());
+            }
+            let uncapped = self.run_state == RunState::FastForward && self.fast_forward_factor == 0;
+            if self.run_state != RunState::Paused && (uncapped || now >= self.next_cycle_deadline) {
+                if now > self.next_cycle_deadline + MAX_LAG_NANOS {
+                    self.next_cycle_deadline = now;
+                }
                 self.step();
-                self.next_cycle_deadline += self.cycle_time_nanos;
+                self.next_cycle_deadline += self.cycle_time_nanos();
                 ticks -= 1;
             } else {
                 thread::sleep(Duration::from_millis(self.cycle_sleep_millis));
-                if self.keyboard.power_off_signal() {
-                    ticks = 0;
-                }
-                if self.keyboard.reset_signal() {
-                    self.reset();
-                }
-            }                
+            }
+            // Input is checked once per frame, however fast or slow we run
+            if now >= self.next_poll_deadline {
+                self.next_poll_deadline = now + FRAME_NANOS;
+                self.handle_signals(&mut ticks);
+            }
+        }
+    }
+
+    fn handle_signals(&mut self, ticks: &mut u64) {
+        if self.keyboard.power_off_signal() {
+            *ticks = 0;
+        }
+        if self.keyboard.reset_signal() {
+            self.reset();
+        }
+        if self.keyboard.pause_signal() {
+            self.paused = !self.paused;
+        }
+        if self.keyboard.slow_motion_signal() {
+            self.slow_motion = !self.slow_motion;
+        }
+        if self.keyboard.speed_up_signal() {
+            let clock_multiplier = self.clock_multiplier;
+            self.set_clock_multiplier(std::cmp::max(clock_multiplier + 1, clock_multiplier * 5 / 4));
+        }
+        if self.keyboard.speed_down_signal() {
+            let clock_multiplier = self.clock_multiplier;
+            self.set_clock_multiplier(std::cmp::min(clock_multiplier - 1, clock_multiplier * 4 / 5));
+        }
+        if self.keyboard.theme_signal() {
+            self.display.next_theme();
+        }
+        if self.keyboard.fullscreen_signal() {
+            self.display.toggle_fullscreen();
+        }
+        if self.keyboard.mute_signal() {
+            let muted = self.buzzer.muted();
+            self.buzzer.set_muted(!muted);
+        }
+        if self.keyboard.volume_up_signal() {
+            let volume = self.buzzer.volume();
+            self.buzzer.set_volume(std::cmp::min(100, volume.saturating_add(VOLUME_STEP)));
+        }
+        if self.keyboard.volume_down_signal() {
+            let volume = self.buzzer.volume();
+            self.buzzer.set_volume(volume.saturating_sub(VOLUME_STEP));
+        }
+        if self.keyboard.frame_advance_signal() && self.paused {
+            (0..self.clock_multiplier).for_each(|_| self.step());
+        }
+        self.fast_forward = self.keyboard.fast_forward_held();
+        self.update_run_state();
+    }
+
+    fn update_run_state(&mut self) {
+        let run_state = if self.paused {
+            RunState::Paused
+        } else if self.fast_forward {
+            RunState::FastForward
+        } else if self.slow_motion {
+            RunState::SlowMotion
+        } else {
+            RunState::Running
+        };
+        if run_state != self.run_state {
+            self.run_state = run_state;
+            self.next_cycle_deadline = Self::now();
+            self.display.show_run_state(run_state);
+        }
+    }
+
+    fn cycle_time_nanos(&self) -> u64 {
+        match self.run_state {
+            RunState::FastForward => self.cycle_time_nanos / std::cmp::max(1, self.fast_forward_factor),
+            RunState::SlowMotion => self.cycle_time_nanos * self.slow_motion_factor,
+            _ => self.cycle_time_nanos
         }
     }
 
-    fn step(&mut self) {
-        if self.cycles % self.clock_multiplier == 0 {
+    // Executes a single instruction without any pacing, e.g. for running
+    // a bounded number of cycles in tests or fuzzing.
+    pub fn step(&mut self) {
+        if self.cycles.is_multiple_of(self.clock_multiplier) {
             if self.regs.dt != 0 {
                 self.regs.dt -= 1
             }
             if self.regs.st != 0 {
-                self.regs.st -= 1
+                self.regs.st -= 1;
+                if self.regs.st == 0 {
+                    self.buzzer.stop();
+                }
             }
+            self.apply_cheats();
         }
-        let instr = self.heap.read_instr(self.regs.pc);
-        self.regs.pc += 2;
-        self.interpret(instr);
+        let op = self.heap.read_op(self.regs.pc);
+        self.regs.pc = self.regs.pc.wrapping_add(2);
+        self.interpret(op);
         self.cycles += 1;
     }
 
-    fn interpret(&mut self, instruction: Instr) {
-        match instruction.instr() & 0xF000 {
-            0x0000 if instruction.instr() & 0xFF == 0xE0 => self.display.clear(),
-            0x0000 if instruction.instr() & 0xFF == 0xEE => self.regs.pc = self.stack.pop(),
-            0x0000 => { }, // SYS addr - ignored
-            0x1000 => self.regs.pc = instruction.addr(),
-            0x2000 => self.call(instruction.addr()),
-            0x3000 => self.skip_if(self.regs.v[instruction.x() as usize] == instruction.byte()),
-            0x4000 => self.skip_if(self.regs.v[instruction.x() as usize] != instruction.byte()),
-            0x5000 => self.skip_if(self.regs.v[instruction.x() as usize] == self.regs.v[instruction.y() as usize]),
-            0x6000 => self.regs.v[instruction.x() as usize] = instruction.byte(),
-            0x7000 => self.regs.v[instruction.x() as usize] = self.regs.v[instruction.x() as usize].overflowing_add(instruction.byte()).0,
-            0x8000 => self.interpret_0x8xxx(instruction),
-            0x9000 => self.skip_if(self.regs.v[instruction.x() as usize] != self.regs.v[instruction.y() as usize]),
-            0xA000 => self.regs.i = instruction.addr(),
-            0xB000 => self.regs.pc = self.regs.v[0] as u16 + instruction.addr(),
-            0xC000 => self.regs.v[instruction.x() as usize] = self.rng.gen::<u8>() & instruction.byte(),
-            0xD000 => self.drw(instruction.nibble(), instruction.x(), instruction.y()),
-            0xE000 => self.skip_on_key_state(instruction.byte() == 0x9E, instruction.x()),
-            0xF000 => self.interpret_0xfxxx(instruction),
-            _ => self.invalid_instruction(instruction)
-        }
-    }
-
-    fn interpret_0x8xxx(&mut self, instruction: Instr) {
-        match instruction.instr() & 0x000F {
-            0x0 => self.regs.v[instruction.x() as usize] = self.regs.v[instruction.y() as usize],
-            0x1 => self.regs.v[instruction.x() as usize] = self.regs.v[instruction.x() as usize] | self.regs.v[instruction.y() as usize],
-            0x2 => self.regs.v[instruction.x() as usize] = self.regs.v[instruction.x() as usize] & self.regs.v[instruction.y() as usize],
-            0x3 => self.regs.v[instruction.x() as usize] = self.regs.v[instruction.x() as usize] ^ self.regs.v[instruction.y() as usize],
-            0x4 => self.add(instruction.x(), instruction.y()),
-            0x5 => self.sub(instruction.x(), instruction.x(), instruction.y()),
-            0x6 => self.shr(instruction.x()),
-            0x7 => self.sub(instruction.x(), instruction.y(), instruction.x()),
-            0xE => self.shl(instruction.x()),
-            _ => self.invalid_instruction(instruction)
+    fn interpret(&mut self, op: Op) {
+        match op {
+            Op::Cls => self.cls(),
+            Op::Ret => self.ret(),
+            Op::Sys => { },
+            Op::Jp(addr) => self.regs.pc = addr,
+            Op::Call(addr) => self.call(addr),
+            Op::SeByte(x, byte) => self.skip_if(self.regs.v[x as usize] == byte),
+            Op::SneByte(x, byte) => self.skip_if(self.regs.v[x as usize] != byte),
+            Op::SeReg(x, y) => self.skip_if(self.regs.v[x as usize] == self.regs.v[y as usize]),
+            Op::LdByte(x, byte) => self.set_register(x, byte),
+            Op::AddByte(x, byte) => self.set_register(x, self.regs.v[x as usize].wrapping_add(byte)),
+            Op::LdReg(x, y) => self.set_register(x, self.regs.v[y as usize]),
+            Op::Or(x, y) => self.set_register(x, self.regs.v[x as usize] | self.regs.v[y as usize]),
+            Op::And(x, y) => self.set_register(x, self.regs.v[x as usize] & self.regs.v[y as usize]),
+            Op::Xor(x, y) => self.set_register(x, self.regs.v[x as usize] ^ self.regs.v[y as usize]),
+            Op::Add(x, y) => self.add(x, y),
+            Op::Sub(x, y) => self.sub(x, x, y),
+            Op::Shr(x) => self.shr(x),
+            Op::Subn(x, y) => self.sub(x, y, x),
+            Op::Shl(x) => self.shl(x),
+            Op::SneReg(x, y) => self.skip_if(self.regs.v[x as usize] != self.regs.v[y as usize]),
+            Op::LdI(addr) => self.set_i(addr),
+            Op::JpV0(addr) => self.regs.pc = self.regs.v[0] as u16 + addr,
+            Op::Rnd(x, byte) => {
+                let random = self.rng.gen::<u8>();
+                self.set_register(x, random & byte)
+            }
+            Op::Drw(x, y, nibble) => self.drw(nibble, x, y),
+            Op::Skp(x) => self.skip_on_key_state(true, x),
+            Op::Sknp(x) => self.skip_on_key_state(false, x),
+            Op::LdFromDt(x) => self.set_register(x, self.regs.dt),
+            Op::LdKey(x) => {
+                let key = self.wait_for_key();
+                self.set_register(x, key)
+            }
+            Op::LdDt(x) => self.set_delay_timer(self.regs.v[x as usize]),
+            Op::LdSt(x) => self.set_sound_timer(self.regs.v[x as usize]),
+            Op::AddI(x) => self.set_i(self.regs.i.wrapping_add(self.regs.v[x as usize] as u16)),
+            Op::LdFont(x) => self.set_i(5 * self.regs.v[x as usize] as u16),
+            Op::Bcd(x) => self.bcd(x),
+            Op::Store => {
+                let registers = self.regs.v;
+                self.write_memory(self.regs.i, &registers)
+            }
+            Op::Load => {
+                let mut registers = [0; NUM_REGS as usize];
+                self.heap.read_bytes(self.regs.i, &mut registers);
+                (0..NUM_REGS).for_each(|x| self.set_register(x, registers[x as usize]));
+            }
+            Op::Invalid => self.invalid_instruction()
         }
     }
 
-    fn interpret_0xfxxx(&mut self, instruction: Instr) {
-        match instruction.instr() & 0x00FF {
-            0x07 => self.regs.v[instruction.x() as usize] = self.regs.dt,
-            0x0A => self.regs.v[instruction.x() as usize] = self.wait_for_key(),
-            0x15 => self.regs.dt = self.regs.v[instruction.x() as usize],
-            0x18 => self.ldst(instruction.x()),
-            0x1E => self.regs.i += self.regs.v[instruction.x() as usize] as u16,
-            0x29 => self.regs.i = 5 * self.regs.v[instruction.x() as usize] as u16,
-            0x33 => self.bcd(instruction.x()),
-            0x55 => self.heap.write_bytes(self.regs.i, &self.regs.v),
-            0x65 => self.regs.v.copy_from_slice(self.heap.read_bytes(self.regs.i, NUM_REGS)),
-            _ => self.invalid_instruction(instruction)
-        }
+    fn invalid_instruction(&mut self) {
+        // Treated like SYS addr: ignoring garbage keeps hostile roms from crashing the host.
+        self.emit(Event::InvalidInstruction);
     }
 
-    fn invalid_instruction(&self, instruction: Instr) {
-        panic!("invalid instruction: {:x}", instruction.instr())
+    fn cls(&mut self) {
+        self.framebuffer.clear();
+        self.emit(Event::Clear);
     }
 
     fn drw(&mut self, nibble: u8, x: u8, y: u8) {
-        let sprite = self.heap.read_sprite(self.regs.i, nibble);
-        let collision = self.display.draw(&sprite, self.regs.v[x as usize], self.regs.v[y as usize]);
-        self.regs.v[LAST_REG] = collision as u8
+        let mut sprite = [0; 15];
+        let sprite = &mut sprite[..nibble as usize];
+        self.heap.read_bytes(self.regs.i, sprite);
+        let collision = self.framebuffer.draw(&Sprite(sprite), self.regs.v[x as usize], self.regs.v[y as usize]);
+        self.set_register(LAST_REG as u8, collision as u8);
+        if collision {
+            self.emit(Event::Collision);
+        }
     }
 
     fn call(&mut self, addr: u16) {
         self.stack.push(self.regs.pc);
         self.regs.pc = addr;
+        self.listener.changed(Change::Stack);
+    }
+
+    fn ret(&mut self) {
+        self.regs.pc = self.stack.pop();
+        self.listener.changed(Change::Stack);
     }
 
     fn bcd(&mut self, x: u8) {
-        self.heap.write_byte(self.regs.i, self.regs.v[x as usize] / 100);
-        self.heap.write_byte(self.regs.i + 1, (self.regs.v[x as usize] / 10) % 10);
-        self.heap.write_byte(self.regs.i + 2, self.regs.v[x as usize] % 10);
+        let val = self.regs.v[x as usize];
+        self.write_memory(self.regs.i, &[val / 100, (val / 10) % 10, val % 10]);
     }
 
     fn skip_on_key_state(&mut self, skip_on_state: bool, x: u8) {
-        let key_state = self.keyboard.pressed(self.regs.v[x as usize]);
+        let key_state = self.keyboard.pressed(self.regs.v[x as usize] & 0xF);
         self.skip_if(key_state == skip_on_state)
     }
 
     fn wait_for_key(&mut self) -> u8 {
-        let t0 = CPU::now();
+        self.emit(Event::KeyWait);
+        // the screen must be current while we block
+        self.display.present(self.framebuffer.pixels());
+        let t0 = Self::now();
         let key = self.keyboard.wait_key();
-        let t1 = CPU::now();
-        self.next_cycle_deadline += t1 - t0;
+        let t1 = Self::now();
+        self.next_cycle_deadline += t1.saturating_sub(t0);
         key
     }
 
     fn skip_if(&mut self, skip: bool) {
         if skip {
-            self.regs.pc += 2
+            self.regs.pc = self.regs.pc.wrapping_add(2)
         }
     }
 
     fn add(&mut self, x: u8, y: u8) {
         let (result, overflow) = self.regs.v[x as usize].overflowing_add(self.regs.v[y as usize]);
-        self.regs.v[LAST_REG] = overflow as u8;
-        self.regs.v[x as usize] = result;
+        self.set_register(LAST_REG as u8, overflow as u8);
+        self.set_register(x, result);
     }
 
     fn sub(&mut self, r: u8, x: u8, y: u8) {
         let rx = self.regs.v[x as usize];
         let ry = self.regs.v[y as usize];
-        self.regs.v[LAST_REG] = (rx > ry) as u8;
-        self.regs.v[r as usize] = rx - ry;
+        self.set_register(LAST_REG as u8, (rx > ry) as u8);
+        self.set_register(r, rx.wrapping_sub(ry));
     }
 
     fn shr(&mut self, x: u8) {
         let rx = self.regs.v[x as usize];
-        self.regs.v[LAST_REG] = rx & 1;
-        self.regs.v[x as usize] = rx >> 1;
+        self.set_register(LAST_REG as u8, rx & 1);
+        self.set_register(x, rx >> 1);
     }
 
     fn shl(&mut self, x: u8) {
         let rx = self.regs.v[x as usize];
-        self.regs.v[LAST_REG] = rx >> 7;
-        self.regs.v[x as usize] = rx << 1;
+        self.set_register(LAST_REG as u8, rx >> 7);
+        self.set_register(x, rx << 1);
     }
 
     fn now() -> u64 {
         SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64
     }
+}
 
-    fn ldst(&mut self, x: u8) {
-        let val = self.regs.v[x as usize];
-        if self.regs.st == 0 && val > 0 {
-            self.buzzer.start();
-        }
-        self.regs.st = val;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_roms_keep_the_builder_speed_and_edge() {
+        let cpu = Builder::new().speed(12).edge(Edge::Clip).rom(&[0x12, 0x00]).build().unwrap();
+        assert!(cpu.rom_info().is_none());
+        assert_eq!(cpu.clock_multiplier(), 12);
+        assert_eq!(cpu.framebuffer().edge(), Edge::Clip);
+    }
+
+    #[test]
+    fn cheats_are_applied_at_frame_boundaries() {
+        let rom = [
+            0x63, 0x00, // 200: V3 = 0
+            0xA3, 0x00, // 202: I = 300
+            0x60, 0x00, // 204: V0 = 0
+            0xF0, 0x55, // 206: store V0 at I
+            0x12, 0x08  // 208: jump to 208
+        ];
+        let cheat = |target, value, enabled| Cheat { name: None, target, value, enabled };
+        let mut cpu = Builder::new()
+            .cheats(vec![
+                cheat(Target::Register(3), 42, true),
+                cheat(Target::Memory(0x300), 7, true),
+                cheat(Target::Register(4), 1, false)
+            ])
+            .speed(2)
+            .rom(&rom)
+            .build()
+            .unwrap();
+        // Frames start every second instruction, before it runs
+        cpu.step();
+        assert_eq!(cpu.memory()[0x300], 7);
+        assert_eq!(cpu.register(3), 0);
+        cpu.step();
+        assert_eq!(cpu.register(3), 0);
+        cpu.step();
+        assert_eq!(cpu.register(3), 42);
+        cpu.step();
+        assert_eq!(cpu.memory()[0x300], 0);
+        cpu.step();
+        assert_eq!(cpu.memory()[0x300], 7);
+        assert_eq!(cpu.register(4), 0);
     }
 }
@@ -1,11 +1,18 @@
-use crate::{arch::Sprite, constants::{HEAP_SIZE, STACK_SIZE}};
+use crate::constants::{HEAP_SIZE, STACK_SIZE};
+
+use super::op::Op;
 
 pub struct Stack {
     sp: usize,
     stack: [u16; STACK_SIZE]
 }
 
-pub struct Heap([u8; HEAP_SIZE]);
+pub struct Heap {
+    bytes: [u8; HEAP_SIZE],
+    // Instructions decoded so far by address, dropped when their bytes are written
+    ops: [Option<Op>; HEAP_SIZE]
+}
+
 pub struct Instr(u16);
 
 impl Stack {
@@ -14,51 +21,98 @@ impl Stack {
     }
 
     pub fn reset(&mut self) {
+        self.sp = 0;
         self.stack = [0; STACK_SIZE]
     }
 
+    // The stack pointer wraps around instead of under- or overflowing,
+    // so unbalanced calls and returns can't take down the host.
     pub fn pop(&mut self) -> u16 {
-        self.sp -= 1;
+        self.sp = (self.sp + STACK_SIZE - 1) % STACK_SIZE;
         self.stack[self.sp]
     }
 
     pub fn push(&mut self, val: u16) {
         self.stack[self.sp] = val;
-        self.sp += 1;
+        self.sp = (self.sp + 1) % STACK_SIZE;
+    }
+
+    pub fn entries(&self) -> &[u16; STACK_SIZE] {
+        &self.stack
+    }
+
+    pub fn set_entry(&mut self, ix: usize, val: u16) {
+        self.stack[ix % STACK_SIZE] = val;
+    }
+
+    pub fn sp(&self) -> usize {
+        self.sp
+    }
+
+    pub fn set_sp(&mut self, sp: usize) {
+        self.sp = sp % STACK_SIZE;
     }
 }
 
 impl Heap {
     pub fn new() -> Heap {
-        Heap([0; HEAP_SIZE])
+        Heap { bytes: [0; HEAP_SIZE], ops: [None; HEAP_SIZE] }
     }
 
     pub fn reset(&mut self) {
-        self.0 = Heap::new().0
+        self.bytes = [0; HEAP_SIZE];
+        self.ops = [None; HEAP_SIZE];
+    }
+
+    // All addresses wrap around at the end of memory.
+    fn offset(addr: u16) -> usize {
+        addr as usize % HEAP_SIZE
     }
 
     pub fn write_bytes(&mut self, addr: u16, src: &[u8]) {
-        let offset = addr as usize;
-        self.0[offset .. offset + src.len()].copy_from_slice(src);
+        for (ix, byte) in src.iter().enumerate() {
+            self.write_byte(addr.wrapping_add(ix as u16), *byte);
+        }
     }
 
     pub fn write_byte(&mut self, addr: u16, src: u8) {
-        self.0[addr as usize] = src;
+        self.bytes[Heap::offset(addr)] = src;
+        // Both instructions the byte is part of
+        self.ops[Heap::offset(addr)] = None;
+        self.ops[Heap::offset(addr.wrapping_sub(1))] = None;
+    }
+
+    pub fn bytes(&self) -> &[u8; HEAP_SIZE] {
+        &self.bytes
     }
 
-    pub fn read_instr(&mut self, addr: u16) -> Instr {
-        let hi = (self.0[addr as usize] as u16) << 8;
-        let lo = self.0[addr as usize + 1] as u16;
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        self.bytes[Heap::offset(addr)]
+    }
+
+    pub fn read_instr(&self, addr: u16) -> Instr {
+        let hi = (self.read_byte(addr) as u16) << 8;
+        let lo = self.read_byte(addr.wrapping_add(1)) as u16;
         Instr(hi | lo)
     }
 
-    pub fn read_bytes(&self, addr: u16, size: u8) -> &[u8] {
-        let address = addr as usize;
-        &self.0[address .. (address + size as usize)]
+    // The instruction at the address, decoded once until it's overwritten.
+    pub fn read_op(&mut self, addr: u16) -> Op {
+        let offset = Heap::offset(addr);
+        match self.ops[offset] {
+            Some(op) => op,
+            None => {
+                let op = Op::decode(self.read_instr(addr));
+                self.ops[offset] = Some(op);
+                op
+            }
+        }
     }
 
-    pub fn read_sprite(&self, addr: u16, size: u8) -> Sprite {
-        Sprite(self.read_bytes(addr, size))
+    pub fn read_bytes(&self, addr: u16, dst: &mut [u8]) {
+        for (ix, byte) in dst.iter_mut().enumerate() {
+            *byte = self.read_byte(addr.wrapping_add(ix as u16));
+        }
     }
 }
 
@@ -87,3 +141,55 @@ impl Instr {
         self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    // Every address, odd ones included, decodes the same with and without the cache.
+    fn assert_cache_matches(heap: &mut Heap) {
+        for addr in 0..HEAP_SIZE as u16 {
+            assert_eq!(heap.read_op(addr), Op::decode(heap.read_instr(addr)), "at {:#05X}", addr);
+        }
+    }
+
+    #[test]
+    fn writes_drop_cached_instructions() {
+        let mut heap = Heap::new();
+        heap.write_bytes(0x200, &[0x60, 0x01, 0x61, 0x02, 0x62, 0x03]);
+        assert_cache_matches(&mut heap);
+        // First byte of the instruction at 0x202
+        heap.write_byte(0x202, 0x71);
+        assert_cache_matches(&mut heap);
+        // Second byte of the instruction at 0x202
+        heap.write_byte(0x203, 0xFF);
+        assert_cache_matches(&mut heap);
+        // The byte before it, which is the second byte of an instruction at 0x201
+        heap.write_byte(0x201, 0xE0);
+        assert_cache_matches(&mut heap);
+        assert_eq!(heap.read_op(0x202), Op::AddByte(1, 0xFF));
+        // An instruction wrapping around the end of memory
+        heap.write_byte(0xFFF, 0x00);
+        heap.write_byte(0x000, 0xEE);
+        assert_cache_matches(&mut heap);
+        assert_eq!(heap.read_op(0xFFF), Op::Ret);
+    }
+
+    #[test]
+    fn random_writes_keep_the_cache_in_sync() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut heap = Heap::new();
+        let mut rom = [0; 256];
+        rng.fill(&mut rom[..]);
+        heap.write_bytes(0x200, &rom);
+        for _ in 0..100 {
+            assert_cache_matches(&mut heap);
+            for _ in 0..16 {
+                heap.write_byte(rng.gen_range(0x1F0..0x310), rng.gen());
+            }
+        }
+        assert_cache_matches(&mut heap);
+    }
+}
@@ -0,0 +1,105 @@
+use super::mem::Instr;
+
+// A decoded instruction, named after its mnemonic. Operands are register
+// numbers unless they're called addr or byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Cls,
+    Ret,
+    // SYS addr - ignored
+    Sys,
+    Jp(u16),
+    Call(u16),
+    SeByte(u8, u8),
+    SneByte(u8, u8),
+    SeReg(u8, u8),
+    LdByte(u8, u8),
+    AddByte(u8, u8),
+    LdReg(u8, u8),
+    Or(u8, u8),
+    And(u8, u8),
+    Xor(u8, u8),
+    Add(u8, u8),
+    Sub(u8, u8),
+    Shr(u8),
+    Subn(u8, u8),
+    Shl(u8),
+    SneReg(u8, u8),
+    LdI(u16),
+    JpV0(u16),
+    Rnd(u8, u8),
+    Drw(u8, u8, u8),
+    Skp(u8),
+    Sknp(u8),
+    LdFromDt(u8),
+    LdKey(u8),
+    LdDt(u8),
+    LdSt(u8),
+    AddI(u8),
+    LdFont(u8),
+    Bcd(u8),
+    // FX55 and FX65 move all registers, whatever X is
+    Store,
+    Load,
+    Invalid
+}
+
+impl Op {
+    pub fn decode(instruction: Instr) -> Op {
+        let (x, y, byte, addr) = (instruction.x(), instruction.y(), instruction.byte(), instruction.addr());
+        match instruction.instr() & 0xF000 {
+            0x0000 if byte == 0xE0 => Op::Cls,
+            0x0000 if byte == 0xEE => Op::Ret,
+            0x0000 => Op::Sys,
+            0x1000 => Op::Jp(addr),
+            0x2000 => Op::Call(addr),
+            0x3000 => Op::SeByte(x, byte),
+            0x4000 => Op::SneByte(x, byte),
+            0x5000 => Op::SeReg(x, y),
+            0x6000 => Op::LdByte(x, byte),
+            0x7000 => Op::AddByte(x, byte),
+            0x8000 => Op::decode_0x8xxx(instruction),
+            0x9000 => Op::SneReg(x, y),
+            0xA000 => Op::LdI(addr),
+            0xB000 => Op::JpV0(addr),
+            0xC000 => Op::Rnd(x, byte),
+            0xD000 => Op::Drw(x, y, instruction.nibble()),
+            0xE000 if byte == 0x9E => Op::Skp(x),
+            0xE000 => Op::Sknp(x),
+            0xF000 => Op::decode_0xfxxx(instruction),
+            _ => Op::Invalid
+        }
+    }
+
+    fn decode_0x8xxx(instruction: Instr) -> Op {
+        let (x, y) = (instruction.x(), instruction.y());
+        match instruction.nibble() {
+            0x0 => Op::LdReg(x, y),
+            0x1 => Op::Or(x, y),
+            0x2 => Op::And(x, y),
+            0x3 => Op::Xor(x, y),
+            0x4 => Op::Add(x, y),
+            0x5 => Op::Sub(x, y),
+            0x6 => Op::Shr(x),
+            0x7 => Op::Subn(x, y),
+            0xE => Op::Shl(x),
+            _ => Op::Invalid
+        }
+    }
+
+    fn decode_0xfxxx(instruction: Instr) -> Op {
+        let x = instruction.x();
+        match instruction.byte() {
+            0x07 => Op::LdFromDt(x),
+            0x0A => Op::LdKey(x),
+            0x15 => Op::LdDt(x),
+            0x18 => Op::LdSt(x),
+            0x1E => Op::AddI(x),
+            0x29 => Op::LdFont(x),
+            0x33 => Op::Bcd(x),
+            0x55 => Op::Store,
+            0x65 => Op::Load,
+            _ => Op::Invalid
+        }
+    }
+}
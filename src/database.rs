@@ -0,0 +1,190 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use serde::Deserialize;
+
+use crate::assets;
+use crate::config::Settings;
+use crate::framebuffer::Edge;
+
+// Behaviours that differ between CHIP-8 platforms, named like in the community
+// chip-8 database. Unset ones aren't known. Only the sprite edge behaviour is
+// emulated, so the database's other quirks (shift, memoryIncrementByX,
+// memoryLeaveIUnchanged, jump, vblank and logic) aren't read at all.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Quirks {
+    // Sprites wrap around the edges of the screen instead of being cut off
+    pub wrap: Option<bool>
+}
+
+// What the database knows about a rom
+#[derive(Clone, Debug)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    // Name of the platform the rom was made for, the first one if several
+    pub platform: Option<String>,
+    // Recommended instructions per frame
+    pub speed: Option<u64>,
+    // Only the ones koro8 emulates
+    pub quirks: Quirks,
+    // What CHIP-8 keys do, e.g. ("left", 4), sorted by key
+    pub keys: Vec<(String, u8)>
+}
+
+// Roms by SHA-1 hash, see crate::rom::hash
+pub struct Database {
+    roms: HashMap<String, RomInfo>
+}
+
+// What's used of programs.json, an array of these
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Program {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    // By hash, since a program can have several versions
+    #[serde(default)]
+    roms: HashMap<String, Rom>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    tickrate: Option<u64>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+    // Quirks that differ from the platform's, by platform id
+    #[serde(default)]
+    quirky_platforms: HashMap<String, Quirks>
+}
+
+// What's used of platforms.json, an array of these
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Platform {
+    id: String,
+    name: String,
+    default_tickrate: Option<u64>,
+    #[serde(default)]
+    quirks: Quirks
+}
+
+impl Quirks {
+    // Returns these quirks with every one that is set in `other` replaced.
+    fn merge(self, other: &Quirks) -> Quirks {
+        Quirks {
+            wrap: other.wrap.or(self.wrap)
+        }
+    }
+}
+
+impl RomInfo {
+    // The settings the database recommends, to go under the user's own for the rom.
+    pub fn settings(&self) -> Settings {
+        Settings {
+            speed: self.speed,
+            edge: self.quirks.wrap.map(|wrap| if wrap { Edge::Wrap } else { Edge::Clip }),
+            ..Settings::default()
+        }
+    }
+
+    pub fn key_hints(&self) -> String {
+        self.keys.iter().map(|(name, key)| format!("{} = {:X}", name, key)).collect::<Vec<_>>().join(", ")
+    }
+}
+
+impl Database {
+    // The database built into koro8, parsed the first time it's needed.
+    pub fn bundled() -> &'static Database {
+        static BUNDLED: OnceLock<Database> = OnceLock::new();
+        BUNDLED.get_or_init(|| Database::parse(assets::ROM_PROGRAMS, assets::ROM_PLATFORMS)
+            .expect("the bundled rom database is valid"))
+    }
+
+    // Takes the contents of programs.json and platforms.json from the
+    // community chip-8 database.
+    pub fn parse(programs: &str, platforms: &str) -> Result<Database, serde_json::Error> {
+        let programs: Vec<Program> = serde_json::from_str(programs)?;
+        let platforms: Vec<Platform> = serde_json::from_str(platforms)?;
+        let platforms: HashMap<_, _> = platforms.into_iter().map(|platform| (platform.id.clone(), platform)).collect();
+        let mut roms = HashMap::new();
+        for program in programs {
+            for (hash, rom) in program.roms {
+                let platform_id = rom.platforms.first();
+                let platform = platform_id.and_then(|id| platforms.get(id));
+                let quirks = platform.map(|platform| platform.quirks).unwrap_or_default();
+                let quirks = match platform_id.and_then(|id| rom.quirky_platforms.get(id)) {
+                    Some(quirky) => quirks.merge(quirky),
+                    None => quirks
+                };
+                let mut keys: Vec<_> = rom.keys.into_iter().collect();
+                keys.sort_by_key(|(name, key)| (*key, name.clone()));
+                let info = RomInfo {
+                    title: program.title.clone(),
+                    authors: program.authors.clone(),
+                    platform: platform.map(|platform| platform.name.clone()).or_else(|| platform_id.cloned()),
+                    speed: rom.tickrate.or_else(|| platform.and_then(|platform| platform.default_tickrate)),
+                    quirks,
+                    keys
+                };
+                roms.insert(hash.to_lowercase(), info);
+            }
+        }
+        Ok(Database { roms })
+    }
+
+    pub fn find(&self, rom_hash: &str) -> Option<&RomInfo> {
+        self.roms.get(rom_hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A made up rom, so that its hash is known without bundling anyone's program
+    const ROM: [u8; 4] = [0x00, 0xE0, 0x12, 0x02];
+
+    fn programs(hash: &str) -> String {
+        format!(r#"[
+            {{
+                "title": "Test Program",
+                "authors": ["Someone"],
+                "roms": {{
+                    "{}": {{
+                        "platforms": ["superchip", "xochip"],
+                        "keys": {{ "left": 4, "right": 6, "fire": 5 }},
+                        "quirkyPlatforms": {{ "superchip": {{ "wrap": true, "shift": false }} }}
+                    }}
+                }}
+            }}
+        ]"#, hash)
+    }
+
+    #[test]
+    fn bundled_database_parses() {
+        Database::bundled();
+    }
+
+    #[test]
+    fn finds_roms_by_hash_with_platform_settings() {
+        let hash = crate::rom::hash(&ROM);
+        // The community database has upper case hashes in places
+        let database = Database::parse(&programs(&hash.to_uppercase()), assets::ROM_PLATFORMS).unwrap();
+        let info = database.find(&hash).expect("the rom is in the database");
+        assert_eq!(info.title, "Test Program");
+        assert_eq!(info.authors, vec![String::from("Someone")]);
+        assert_eq!(info.platform.as_deref(), Some("SUPER-CHIP 1.1"));
+        assert_eq!(info.speed, Some(30));
+        assert_eq!(info.quirks, Quirks { wrap: Some(true) });
+        assert_eq!(info.key_hints(), "left = 4, fire = 5, right = 6");
+        let settings = info.settings();
+        assert_eq!(settings.speed, Some(30));
+        assert_eq!(settings.edge, Some(Edge::Wrap));
+        assert!(database.find(&crate::rom::hash(&[0x12, 0x00])).is_none());
+    }
+}
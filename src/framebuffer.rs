@@ -0,0 +1,93 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::arch::{PIXELS, WIDTH, HEIGHT, Sprite};
+
+// What happens to the parts of a sprite past the edge of the screen. Where
+// a sprite starts always wraps around, e.g. x = 70 draws at x = 6.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Edge {
+    // They're drawn on the opposite side
+    Wrap,
+    // They're cut off, like on the COSMAC VIP
+    Clip
+}
+
+impl FromStr for Edge {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Edge, String> {
+        match s {
+            "wrap" => Ok(Edge::Wrap),
+            "clip" => Ok(Edge::Clip),
+            _ => Err(String::from("must be wrap or clip"))
+        }
+    }
+}
+
+// The CHIP-8 screen, row by row. Drawn on by the CPU and handed to the
+// display backend once per frame.
+#[derive(Clone)]
+pub struct Framebuffer {
+    pixels: [bool;PIXELS],
+    edge: Edge
+}
+
+impl Framebuffer {
+    pub fn new(edge: Edge) -> Framebuffer {
+        Framebuffer { pixels: [false;PIXELS], edge }
+    }
+
+    pub fn pixels(&self) -> &[bool;PIXELS] {
+        &self.pixels
+    }
+
+    pub fn set_pixels(&mut self, pixels: &[bool;PIXELS]) {
+        self.pixels = *pixels;
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.pixels[(y % HEIGHT) * WIDTH + x % WIDTH]
+    }
+
+    pub fn edge(&self) -> Edge {
+        self.edge
+    }
+
+    pub fn set_edge(&mut self, edge: Edge) {
+        self.edge = edge;
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels = [false;PIXELS];
+    }
+
+    // XORs the sprite onto the screen, 8 pixels wide and a row per byte.
+    // Returns whether any lit pixel was turned off.
+    pub fn draw(&mut self, sprite: &Sprite, x: u8, y: u8) -> bool {
+        let (x, y) = (x as usize % WIDTH, y as usize % HEIGHT);
+        let mut collision = false;
+        for (row_ix, row) in sprite.0.iter().enumerate() {
+            if self.edge == Edge::Clip && y + row_ix >= HEIGHT {
+                break;
+            }
+            let py = (y + row_ix) % HEIGHT;
+            for col_ix in 0..8 {
+                if self.edge == Edge::Clip && x + col_ix >= WIDTH {
+                    break;
+                }
+                let px = (x + col_ix) % WIDTH;
+                let ix = py * WIDTH + px;
+                let old_pixel = self.pixels[ix];
+                let new_pixel = ((row >> (7 - col_ix)) & 1) == 1;
+                self.pixels[ix] = old_pixel ^ new_pixel;
+                if old_pixel && new_pixel {
+                    collision = true;
+                }
+            }
+        }
+        collision
+    }
+}
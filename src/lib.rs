@@ -2,3 +2,12 @@ pub mod cpu;
 pub mod arch;
 pub mod peripherals;
 pub mod constants;
+pub mod rom;
+pub mod config;
+pub mod database;
+pub mod cheats;
+pub mod framebuffer;
+pub mod phosphor;
+pub mod theme;
+pub mod assets;
+pub mod soundpack;
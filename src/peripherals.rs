@@ -1 +1,5 @@
+pub mod headless;
+#[cfg(feature = "sdl")]
 pub mod sdl;
+#[cfg(feature = "terminal")]
+pub mod terminal;
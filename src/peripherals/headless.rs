@@ -0,0 +1,104 @@
+use crate::arch::{PIXELS, NUM_KEYS, RunState};
+
+// Peripherals that don't need a window or sound card, for running roms
+// in tests, benchmarks and fuzzers.
+
+pub struct Display;
+
+impl crate::arch::Display for Display {
+    fn present(&mut self, _pixels: &[bool;PIXELS]) {
+        // nothing to present to, the CPU's framebuffer has the pixels
+    }
+
+    fn show_run_state(&mut self, _state: RunState) {
+        // nothing to show it on
+    }
+
+    fn show_speed(&mut self, _instructions_per_frame: u64) {
+        // nothing to show it on
+    }
+
+    fn show_title(&mut self, _title: Option<&str>) {
+        // nothing to show it on
+    }
+
+    fn next_theme(&mut self) {
+        // nothing to theme
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        // nothing to fill
+    }
+
+    fn reset(&mut self) {
+        // nothing to reset
+    }
+}
+
+// A keyboard whose key states are set by the caller rather than a user.
+// Waiting for a key returns immediately so that execution never blocks.
+pub struct Keyboard {
+    key_states: [bool;NUM_KEYS]
+}
+
+impl Keyboard {
+    pub fn new() -> Keyboard {
+        Keyboard { key_states: [false;NUM_KEYS] }
+    }
+
+    pub fn set_pressed(&mut self, key: u8, pressed: bool) {
+        self.key_states[key as usize % NUM_KEYS] = pressed;
+    }
+}
+
+impl Default for Keyboard {
+    fn default() -> Keyboard {
+        Keyboard::new()
+    }
+}
+
+impl crate::arch::Keyboard for Keyboard {
+    fn pressed(&mut self, key: u8) -> bool {
+        self.key_states[key as usize % NUM_KEYS]
+    }
+
+    fn wait_key(&mut self) -> u8 {
+        self.key_states.iter().position(|pressed| *pressed).unwrap_or(0) as u8
+    }
+
+    fn reset(&mut self) {
+        self.key_states = [false;NUM_KEYS];
+    }
+}
+
+pub struct Buzzer;
+
+impl crate::arch::Buzzer for Buzzer {
+    fn start(&mut self, _frames: u8) {
+        // silence is golden
+    }
+
+    fn stop(&mut self) {
+        // nothing to do here
+    }
+
+    fn set_volume(&mut self, _percent: u8) {
+        // nothing to turn up
+    }
+
+    fn volume(&self) -> u8 {
+        0
+    }
+
+    fn set_muted(&mut self, _muted: bool) {
+        // always is
+    }
+
+    fn muted(&self) -> bool {
+        true
+    }
+
+    fn reset(&mut self) {
+        // nothing to do here
+    }
+}
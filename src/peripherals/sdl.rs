@@ -1,3 +1,4 @@
 pub mod display;
 pub mod keyboard;
 pub mod buzzer;
+pub mod beeper;
@@ -0,0 +1,161 @@
+use std::f32::consts::PI;
+
+use sdl2::{audio::{AudioCallback, AudioDevice, AudioSpecDesired}, Sdl};
+
+use crate::arch::Waveform;
+
+// Time for the tone to fade in or out, so that starting and stopping doesn't click
+const ENVELOPE_SECONDS: f32 = 0.005;
+
+// A synthesized tone, for roms that beep too often or too briefly for sound clips.
+pub struct Beeper {
+    device: AudioDevice<Tone>,
+    volume: u8,
+    muted: bool
+}
+
+// A band-limited waveform generator, also used for the buzzer's tone mode.
+pub struct Oscillator {
+    waveform: Waveform,
+    sample_rate: f32,
+    // Position within a period, from 0 to 1
+    phase: f32,
+    phase_step: f32
+}
+
+struct Tone {
+    oscillator: Oscillator,
+    channels: usize,
+    volume: f32,
+    gain: f32,
+    gain_step: f32,
+    on: bool
+}
+
+// Smooths a step of a naive waveform at phase 0 over the samples around it,
+// which takes out most of the aliasing (PolyBLEP).
+fn poly_blep(phase: f32, phase_step: f32) -> f32 {
+    if phase < phase_step {
+        let t = phase / phase_step;
+        t + t - t * t - 1.0
+    } else if phase > 1.0 - phase_step {
+        let t = (phase - 1.0) / phase_step;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
+
+impl Oscillator {
+    pub fn new(waveform: Waveform, frequency: u32, sample_rate: u32) -> Oscillator {
+        let mut oscillator = Oscillator {
+            waveform,
+            sample_rate: std::cmp::max(1, sample_rate) as f32,
+            phase: 0.0,
+            phase_step: 0.0
+        };
+        oscillator.set_frequency(frequency);
+        oscillator
+    }
+
+    pub fn set_frequency(&mut self, frequency: u32) {
+        // Above the Nyquist frequency there's nothing left to hear
+        let frequency = (std::cmp::max(1, frequency) as f32).min(self.sample_rate / 2.0);
+        self.phase_step = frequency / self.sample_rate;
+    }
+
+    // The next sample, from -1 to 1
+    pub fn next_sample(&mut self) -> f32 {
+        let phase = self.phase;
+        self.phase = (self.phase + self.phase_step).fract();
+        match self.waveform {
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+            Waveform::Square => {
+                let naive = if phase < 0.5 { 1.0 } else { -1.0 };
+                naive + poly_blep(phase, self.phase_step) - poly_blep((phase + 0.5).fract(), self.phase_step)
+            }
+        }
+    }
+}
+
+impl AudioCallback for Tone {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for frame in out.chunks_mut(self.channels) {
+            self.gain = if self.on {
+                (self.gain + self.gain_step).min(1.0)
+            } else {
+                (self.gain - self.gain_step).max(0.0)
+            };
+            let sample = self.oscillator.next_sample() * self.gain * self.volume;
+            frame.iter_mut().for_each(|out| *out = sample);
+        }
+    }
+}
+
+impl Beeper {
+    // Volume is in percent.
+    pub fn new(sdl: &Sdl, waveform: Waveform, frequency: u32, volume: u8) -> Option<Beeper> {
+        let audio = sdl.audio().ok()?;
+        let desired = AudioSpecDesired { freq: Some(44100), channels: Some(1), samples: Some(512) };
+        let device = audio.open_playback(None, &desired, |spec| {
+            let sample_rate = std::cmp::max(1, spec.freq) as u32;
+            Tone {
+                oscillator: Oscillator::new(waveform, frequency, sample_rate),
+                channels: std::cmp::max(1, spec.channels as usize),
+                volume: 0.0,
+                gain: 0.0,
+                gain_step: 1.0 / (ENVELOPE_SECONDS * sample_rate as f32),
+                on: false
+            }
+        }).ok()?;
+        // Plays silence until started
+        device.resume();
+        let mut beeper = Beeper { device, volume, muted: false };
+        beeper.apply_volume();
+        Some(beeper)
+    }
+
+    pub fn set_frequency(&mut self, frequency: u32) {
+        self.device.lock().oscillator.set_frequency(frequency);
+    }
+
+    fn apply_volume(&mut self) {
+        let percent = if self.muted { 0 } else { self.volume };
+        // A full scale square wave is much louder than the sound clips
+        self.device.lock().volume = percent as f32 / 100.0 * 0.25;
+    }
+}
+
+impl crate::arch::Buzzer for Beeper {
+    fn start(&mut self, _frames: u8) {
+        self.device.lock().on = true;
+    }
+
+    fn stop(&mut self) {
+        self.device.lock().on = false;
+    }
+
+    fn set_volume(&mut self, percent: u8) {
+        self.volume = percent.min(100);
+        self.apply_volume();
+    }
+
+    fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+        self.apply_volume();
+    }
+
+    fn muted(&self) -> bool {
+        self.muted
+    }
+
+    fn reset(&mut self) {
+        self.device.lock().on = false;
+    }
+}
@@ -1,53 +1,233 @@
-use rand::{RngCore, Rng};
-use sdl2::{Sdl, mixer::{DEFAULT_FORMAT, DEFAULT_CHANNELS, Chunk, Sdl2MixerContext}, AudioSubsystem};
+use std::{collections::HashMap, path::Path, time::{Duration, Instant}};
+
+use rand::{Rng, RngCore};
+use sdl2::{Sdl, mixer::{DEFAULT_FORMAT, DEFAULT_CHANNELS, Channel, Chunk, LoaderRWops, Sdl2MixerContext}, rwops::RWops, AudioSubsystem};
+
+use crate::arch::{Event, SoundMode, Waveform};
+use crate::assets;
+use crate::soundpack::SoundPack;
+
+use super::beeper::Oscillator;
+
+// How long a truncated clip takes to fade out
+const FADE_OUT_MILLIS: i32 = 100;
+// Pitch of the tone. The looped chunk is a second long, so it holds a whole
+// number of periods and doesn't click.
+const TONE_HZ: u32 = 441;
+const TONE_AMPLITUDE: f32 = (i16::MAX / 4) as f32;
+// Fading the tone in and out keeps it from clicking
+const TONE_FADE_MILLIS: i32 = 5;
 
 pub struct Buzzer {
     _audio: AudioSubsystem,
     _mixer: Sdl2MixerContext,
-    chunks: Vec<(Chunk, u32)>,
+    mode: SoundMode,
+    pack: SoundPack,
+    // Loaded clips of the pack, in the same order
+    chunks: Vec<Chunk>,
+    tone: Option<Chunk>,
+    // Channel of the sound started last
+    playing: Option<Channel>,
+    volume: u8,
+    muted: bool,
+    // New sounds aren't started while this many are playing
+    max_playing: Option<usize>,
     rng: Box<dyn RngCore>
 }
 
+// Plays clips when events happen, at most once per cooldown for each event.
+pub struct EventSounds {
+    chunks: HashMap<Event, Vec<Chunk>>,
+    cooldown: Duration,
+    last_played: HashMap<Event, Instant>,
+    max_playing: Option<usize>,
+    rng: Box<dyn RngCore>
+}
+
+// Whether another sound may start with at most `max_playing` playing at once.
+fn may_play(max_playing: Option<usize>) -> bool {
+    match max_playing {
+        Some(max_playing) => (sdl2::mixer::get_playing_channels_number() as usize) < max_playing,
+        None => true
+    }
+}
+
+// Loads a clip from the directory, or from the built-in sounds without one.
+fn load_chunk(dir: Option<&Path>, file: &Path) -> Option<Chunk> {
+    match dir {
+        Some(dir) => Chunk::from_file(dir.join(file)).ok(),
+        None => RWops::from_bytes(assets::sound_file(file.to_str()?)?).ok()?.load_wav().ok()
+    }
+}
+
 impl Buzzer {
-    pub fn new(sdl: &Sdl, sound_dir_path: &str, channels: i32, rng: Box<dyn RngCore>) -> Option<Buzzer> {
+    // Clips of the pack that can't be loaded are left out.
+    pub fn new(
+        sdl: &Sdl,
+        mut pack: SoundPack,
+        channels: i32,
+        mode: SoundMode,
+        rng: Box<dyn RngCore>
+    ) -> Option<Buzzer> {
         let audio = sdl.audio().ok()?;
         sdl2::mixer::open_audio(44100, DEFAULT_FORMAT, DEFAULT_CHANNELS, 1024).ok()?;
         let mixer = sdl2::mixer::init(sdl2::mixer::InitFlag::MP3).ok()?;
         sdl2::mixer::allocate_channels(channels);
 
-        let sound_dir = std::fs::read_dir(sound_dir_path).ok()?;
-        let chunks: Vec<_> = sound_dir.filter_map(|file| {
-            let path = file.ok()?.path();
-            sdl2::mixer::Chunk::from_file(path).ok().map(|c| (c, 0))
-        }).collect();
+        let (chunks, tone) = if mode == SoundMode::Tone {
+            (Vec::new(), Some(Buzzer::create_tone()?))
+        } else {
+            let loaded: Vec<_> = pack.clips.iter().map(|clip| {
+                let mut chunk = load_chunk(pack.dir.as_deref(), &clip.file)?;
+                chunk.set_volume(clip.volume.unwrap_or(100).min(100) as i32 * sdl2::mixer::MAX_VOLUME / 100);
+                Some(chunk)
+            }).collect();
+            pack.retain(|ix, _| loaded[ix].is_some());
+            let chunks: Vec<_> = loaded.into_iter().flatten().collect();
+            if chunks.is_empty() {
+                return None;
+            }
+            (chunks, None)
+        };
 
         let buzzer = Buzzer {
             _audio: audio,
             _mixer: mixer,
+            mode,
+            pack,
             chunks,
+            tone,
+            playing: None,
+            volume: 100,
+            muted: false,
+            max_playing: None,
             rng
         };
         Some(buzzer)
     }
+
+    // A second of the beeper's square wave in the mixer's format, to be looped.
+    fn create_tone() -> Option<Chunk> {
+        let (sample_rate, _, channels) = sdl2::mixer::query_spec().ok()?;
+        let mut oscillator = Oscillator::new(Waveform::Square, TONE_HZ, sample_rate as u32);
+        // Samples of all channels are interleaved
+        let samples: Vec<i16> = (0..sample_rate)
+            .flat_map(|_| std::iter::repeat_n((oscillator.next_sample() * TONE_AMPLITUDE) as i16, channels as usize))
+            .collect();
+        Chunk::from_raw_buffer(samples.into_boxed_slice()).ok()
+    }
+
+    // Doesn't start a new sound while `max_playing` are playing, None for as many
+    // as there are channels. Event sounds made afterwards share the limit.
+    pub fn set_max_playing(&mut self, max_playing: Option<usize>) {
+        self.max_playing = max_playing;
+    }
+
+    pub fn max_playing(&self) -> Option<usize> {
+        self.max_playing
+    }
+
+    fn apply_volume(&self) {
+        let volume = if self.muted { 0 } else { self.volume as i32 * sdl2::mixer::MAX_VOLUME / 100 };
+        sdl2::mixer::Channel::all().set_volume(volume);
+    }
+
+    // Sounds for the events the pack binds clips to, if any. The clips that
+    // can't be loaded are left out.
+    pub fn event_sounds(&self, rng: Box<dyn RngCore>) -> Option<EventSounds> {
+        let dir = self.pack.dir.as_deref();
+        let chunks: HashMap<_, _> = self.pack.events.iter()
+            .map(|(event, files)| (*event, files.iter().filter_map(|file| load_chunk(dir, file)).collect::<Vec<_>>()))
+            .filter(|(_, chunks)| !chunks.is_empty())
+            .collect();
+        if chunks.is_empty() {
+            return None;
+        }
+        let event_sounds = EventSounds {
+            chunks,
+            cooldown: self.pack.event_cooldown,
+            last_played: HashMap::new(),
+            max_playing: self.max_playing,
+            rng
+        };
+        Some(event_sounds)
+    }
+
+    fn play_clip(&mut self, frames: u8) -> Result<Channel, String> {
+        let chunk_index = self.pack.choose(frames, self.rng.as_mut()).ok_or("no sounds to play")?;
+        sdl2::mixer::Channel::all().play(&self.chunks[chunk_index], 0)
+    }
 }
 
 impl crate::arch::Buzzer for Buzzer {
-    fn start(&mut self) {
-        // Ensure that we get a good mix of sounds
-        let min_play_count = self.chunks.iter().map(|(_, play_count)| *play_count).min_by(core::cmp::Ord::cmp).unwrap();
-        let mut eligible_chunks: Vec<_> = self.chunks.iter_mut().filter(|(_, play_count)| *play_count == min_play_count).collect();
-
-        let chunk_index = self.rng.gen::<usize>() % eligible_chunks.len();
-        let chunk = &mut eligible_chunks[chunk_index];
-        let _ = sdl2::mixer::Channel::all().play(&chunk.0, 0);
-        chunk.1 += 1;
+    fn start(&mut self, frames: u8) {
+        if self.muted || !may_play(self.max_playing) {
+            return;
+        }
+        let playing = match &self.tone {
+            Some(tone) => sdl2::mixer::Channel::all().fade_in(tone, -1, TONE_FADE_MILLIS),
+            None => self.play_clip(frames)
+        };
+        self.playing = playing.ok();
     }
 
     fn stop(&mut self) {
-        // can't stop the korone
+        let channel = match self.playing.take() {
+            Some(channel) => channel,
+            None => return
+        };
+        match self.mode {
+            SoundMode::Tone => {
+                channel.fade_out(TONE_FADE_MILLIS);
+            }
+            SoundMode::Truncate => {
+                channel.fade_out(FADE_OUT_MILLIS);
+            }
+            // can't stop the korone
+            SoundMode::Complete => { }
+        }
+    }
+
+    // Sets the volume of all channels, including event sounds, from 0 to 100 percent.
+    fn set_volume(&mut self, percent: u8) {
+        self.volume = percent.min(100);
+        self.apply_volume();
+    }
+
+    fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    // Muting keeps the volume for unmuting.
+    fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+        self.apply_volume();
+    }
+
+    fn muted(&self) -> bool {
+        self.muted
     }
 
     fn reset(&mut self) {
-        // nothing to do here
+        if self.mode != SoundMode::Complete {
+            sdl2::mixer::Channel::all().halt();
+        }
+        self.playing = None;
+    }
+}
+
+impl crate::arch::Listener for EventSounds {
+    fn notify(&mut self, event: Event) {
+        let chunks = match self.chunks.get(&event) {
+            Some(chunks) => chunks,
+            None => return
+        };
+        let now = Instant::now();
+        if matches!(self.last_played.get(&event), Some(last) if now.duration_since(*last) < self.cooldown) || !may_play(self.max_playing) {
+            return;
+        }
+        self.last_played.insert(event, now);
+        let chunk = &chunks[self.rng.gen_range(0..chunks.len())];
+        let _ = sdl2::mixer::Channel::all().play(chunk, 0);
     }
 }
@@ -1,87 +1,323 @@
-use sdl2::{render::{Canvas, Texture}, video::Window, Sdl, rect::Rect};
+use sdl2::{render::{BlendMode, Canvas, Texture, TextureCreator}, video::{FullscreenType, Window, WindowContext}, Sdl, rect::Rect, pixels::{Color, PixelFormatEnum}};
+use sdl2::image::LoadTexture;
 
-use crate::arch::{PIXELS, WIDTH, SCALE, HEIGHT};
+use crate::arch::{PIXELS, WIDTH, HEIGHT, RunState, Scaling};
+use crate::phosphor::Phosphor;
+use crate::theme::{Image, Rgb, Theme};
+
+// 8x5 icons shown in the top right corner while not running normally
+const PAUSED_ICON: [u8; 5] = [0x6C, 0x6C, 0x6C, 0x6C, 0x6C];
+const FAST_FORWARD_ICON: [u8; 5] = [0x88, 0xCC, 0xEE, 0xCC, 0x88];
+const SLOW_MOTION_ICON: [u8; 5] = [0x80, 0xC0, 0xE0, 0xC0, 0x80];
+
+// A theme with its images loaded
+pub struct Skin<'a> {
+    title: String,
+    pixel: Option<Texture<'a>>,
+    background: Option<Texture<'a>>,
+    foreground_colour: Rgb,
+    background_colour: Rgb
+}
 
 pub struct Display<'a> {
     canvas: Canvas<Window>,
-    pixel: Texture<'a>,
-    background: Texture<'a>,
-    pixels: [bool;PIXELS]
+    skins: Vec<Skin<'a>>,
+    skin: usize,
+    // Lit pixels are drawn as plain squares by uploading the whole framebuffer
+    // at once, when the skin has no pixel image or plain pixels were asked for.
+    framebuffer: Texture<'a>,
+    plain_pixels: bool,
+    scaling: Scaling,
+    // Window size when last rendered, so that resizing renders again
+    rendered_size: (u32, u32),
+    run_state: RunState,
+    speed: u64,
+    rom_title: Option<String>,
+    key_hints: Option<String>,
+    // As last presented
+    pixels: [bool;PIXELS],
+    phosphor: Phosphor,
+    dirty: bool
+}
+
+fn colour(rgb: Rgb) -> Color {
+    Color::RGB(rgb.0, rgb.1, rgb.2)
+}
+
+impl <'a> Skin<'a> {
+    pub fn load(texture_creator: &'a TextureCreator<WindowContext>, theme: &Theme) -> Result<Skin<'a>, String> {
+        let load_texture = |image: &Option<Image>| match image {
+            Some(Image::File(path)) => texture_creator.load_texture(path)
+                .map(Some)
+                .map_err(|err| format!("can't load {}: {}", path.display(), err)),
+            Some(Image::Embedded(bytes)) => texture_creator.load_texture_bytes(bytes)
+                .map(Some)
+                .map_err(|err| format!("can't load the {} theme: {}", theme.name, err)),
+            None => Ok(None)
+        };
+        let mut pixel = load_texture(&theme.pixel)?;
+        if let Some(pixel) = &mut pixel {
+            // Fading pixels are drawn translucent
+            pixel.set_blend_mode(BlendMode::Blend);
+        }
+        Ok(Skin {
+            title: theme.title.clone(),
+            pixel,
+            background: load_texture(&theme.background)?,
+            foreground_colour: theme.foreground_colour(),
+            background_colour: theme.background_colour()
+        })
+    }
 }
 
 impl <'a> Display<'a> {
-    pub fn create_canvas(sdl: &Sdl) -> Option<Canvas<Window>> {
+    // The window starts out at `scale` times the CHIP-8 screen size. Textures
+    // created afterwards are filtered if the scaling is smooth.
+    pub fn create_canvas(sdl: &Sdl, scale: usize, scaling: Scaling, fullscreen: bool) -> Option<Canvas<Window>> {
         let video  = sdl.video().ok()?;
-        let window = video
-            .window("koro8", (WIDTH * SCALE) as u32, (HEIGHT * SCALE) as u32)
-            .position_centered()
-            .build()
-            .ok()?;
-        window.into_canvas().accelerated().build().ok()
+        let mut window = video.window("koro8", (WIDTH * scale) as u32, (HEIGHT * scale) as u32);
+        window.position_centered().resizable();
+        if fullscreen {
+            window.fullscreen_desktop();
+        }
+        let canvas = window.build().ok()?.into_canvas().accelerated().build().ok()?;
+        let quality = if scaling == Scaling::Smooth { "linear" } else { "nearest" };
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", quality);
+        Some(canvas)
+    }
+
+    pub fn create_framebuffer(texture_creator: &TextureCreator<WindowContext>) -> Option<Texture<'_>> {
+        // Plain pixels stay sharp whatever the scaling
+        let quality = sdl2::hint::get("SDL_RENDER_SCALE_QUALITY");
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+        let texture = texture_creator
+            .create_texture_streaming(PixelFormatEnum::ARGB8888, WIDTH as u32, HEIGHT as u32);
+        if let Some(quality) = quality {
+            sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", &quality);
+        }
+        let mut texture = texture.ok()?;
+        texture.set_blend_mode(BlendMode::Blend);
+        Some(texture)
     }
 
+    // The first skin is shown first, the next theme hotkey cycles through the others.
     pub fn new(
         canvas: Canvas<Window>,
-        pixel: Texture<'a>,
-        background: Texture<'a>
+        skins: Vec<Skin<'a>>,
+        framebuffer: Texture<'a>,
+        plain_pixels: bool,
+        scaling: Scaling,
+        phosphor: Phosphor
     ) -> Option<Display<'a>> {
+        if skins.is_empty() {
+            return None;
+        }
         let display = Display {
             canvas,
-            pixel,
-            background,
-            pixels: [false;PIXELS]
+            skins,
+            skin: 0,
+            framebuffer,
+            plain_pixels,
+            scaling,
+            rendered_size: (0, 0),
+            run_state: RunState::Running,
+            speed: 0,
+            rom_title: None,
+            key_hints: None,
+            pixels: [false;PIXELS],
+            phosphor,
+            dirty: true
         };
         Some(display)
     }
 }
 
-impl <'a> crate::arch::Display for Display<'a> {
-    fn clear(&mut self) {
-        self.pixels = [false;PIXELS];
+// The part of the screen covered by cell (x, y) of a grid of `cols` by `rows`
+// cells over the viewport. Cells fill the viewport without gaps when it isn't
+// a whole multiple of the grid size.
+fn cell_rect(viewport: Rect, cols: usize, rows: usize, x: usize, y: usize) -> Rect {
+    let (width, height) = (viewport.width() as usize, viewport.height() as usize);
+    let left = x * width / cols;
+    let top = y * height / rows;
+    let right = (x + 1) * width / cols;
+    let bottom = (y + 1) * height / rows;
+    Rect::new(
+        viewport.x() + left as i32,
+        viewport.y() + top as i32,
+        std::cmp::max(1, right - left) as u32,
+        std::cmp::max(1, bottom - top) as u32
+    )
+}
+
+impl <'a> Display<'a> {
+    // Where the CHIP-8 screen goes in a window of the given size, centred with
+    // black bars on the sides that don't fit the aspect ratio.
+    fn viewport(&self, (width, height): (u32, u32)) -> Rect {
+        let (screen_width, screen_height) = (WIDTH as u32, HEIGHT as u32);
+        let (viewport_width, viewport_height) = match self.scaling {
+            Scaling::Integer => {
+                let scale = std::cmp::max(1, std::cmp::min(width / screen_width, height / screen_height));
+                (scale * screen_width, scale * screen_height)
+            }
+            Scaling::Nearest | Scaling::Smooth => if width * screen_height > height * screen_width {
+                (height * screen_width / screen_height, height)
+            } else {
+                (width, width * screen_height / screen_width)
+            }
+        };
+        Rect::new(
+            (width as i32 - viewport_width as i32) / 2,
+            (height as i32 - viewport_height as i32) / 2,
+            std::cmp::max(1, viewport_width),
+            std::cmp::max(1, viewport_height)
+        )
+    }
+
+    fn present_background(&mut self, viewport: Rect) {
+        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.clear();
+        let skin = &self.skins[self.skin];
+        match &skin.background {
+            Some(background) => self.canvas.copy(background, None, viewport).unwrap(),
+            None => {
+                self.canvas.set_draw_color(colour(skin.background_colour));
+                self.canvas.fill_rect(viewport).unwrap();
+            }
+        }
     }
 
-    fn draw(&mut self, sprite: &crate::arch::Sprite, x: u8, y: u8) -> bool {
-        let mut pixel_changed = false;
-        for (row, row_ix) in sprite.0.iter().zip(0..sprite.0.len()) {
+    fn present_pixels(&mut self, viewport: Rect) {
+        let brightness = self.phosphor.apply(&self.pixels);
+        let canvas = &mut self.canvas;
+        let skin = &mut self.skins[self.skin];
+        match &mut skin.pixel {
+            Some(pixel) if !self.plain_pixels => {
+                (0..HEIGHT).for_each(|y| {
+                    (0..WIDTH).for_each(|x| {
+                        let alpha = brightness[y * WIDTH + x];
+                        if alpha > 0 {
+                            pixel.set_alpha_mod(alpha);
+                            canvas.copy(pixel, None, cell_rect(viewport, WIDTH, HEIGHT, x, y)).unwrap();
+                        }
+                    })
+                });
+                pixel.set_alpha_mod(255);
+            }
+            _ => {
+                let Rgb(r, g, b) = skin.foreground_colour;
+                let rgb = (r as u32) << 16 | (g as u32) << 8 | b as u32;
+                let framebuffer = &mut self.framebuffer;
+                let _ = framebuffer.with_lock(None, |buffer, pitch| {
+                    for (y, row) in brightness.chunks(WIDTH).enumerate() {
+                        for (x, alpha) in row.iter().enumerate() {
+                            let argb = (*alpha as u32) << 24 | rgb;
+                            let offset = y * pitch + x * 4;
+                            buffer[offset .. offset + 4].copy_from_slice(&argb.to_ne_bytes());
+                        }
+                    }
+                });
+                canvas.copy(framebuffer, None, viewport).unwrap();
+            }
+        }
+    }
+
+    fn present_run_state(&mut self, viewport: Rect) {
+        let icon = match self.run_state {
+            RunState::Running => return,
+            RunState::Paused => PAUSED_ICON,
+            RunState::FastForward => FAST_FORWARD_ICON,
+            RunState::SlowMotion => SLOW_MOTION_ICON
+        };
+        let skin = &self.skins[self.skin];
+        self.canvas.set_draw_color(colour(skin.foreground_colour));
+        // Icon pixels are half the size of CHIP-8 pixels
+        let (cols, rows) = (WIDTH * 2, HEIGHT * 2);
+        let left = cols - 9;
+        for (row_ix, row) in icon.iter().enumerate() {
             for col_ix in 0..8 {
-                let py = (row_ix + y as usize) % HEIGHT;
-                let px = (col_ix + x as usize) % WIDTH;
-                let ix = py * WIDTH + px;
-                let old_pixel = self.pixels[ix];
-                let new_pixel = ((row >> (7 - col_ix)) & 1) == 1;
-                self.pixels[ix] = old_pixel ^ new_pixel;
-                if old_pixel && new_pixel {
-                    pixel_changed = true;
+                if (row >> (7 - col_ix)) & 1 == 1 {
+                    let dst_rect = cell_rect(viewport, cols, rows, left + col_ix, row_ix + 1);
+                    match &skin.pixel {
+                        Some(pixel) => self.canvas.copy(pixel, None, dst_rect).unwrap(),
+                        None => self.canvas.fill_rect(dst_rect).unwrap()
+                    }
                 }
             }
         }
-        self.present();
-        pixel_changed
     }
 
-    fn present(&mut self) {
+    fn update_title(&mut self) {
+        let skin_title = &self.skins[self.skin].title;
+        let mut title = match &self.rom_title {
+            Some(rom_title) => format!("{} - {} - {} instructions/frame", rom_title, skin_title, self.speed),
+            None => format!("{} - {} instructions/frame", skin_title, self.speed)
+        };
+        if let Some(key_hints) = &self.key_hints {
+            title.push_str(&format!(" - keys: {}", key_hints));
+        }
+        let _ = self.canvas.window_mut().set_title(&title);
+    }
+}
 
-        let pixels = &self.pixels;
-        let pixel = &self.pixel;
-        let canvas = &mut self.canvas;
-        let src_rect = Rect::new(0, 0, 16, 16);
-        canvas.copy(
-            &self.background,
-            Rect::new(0, 0, (WIDTH*SCALE) as u32, (HEIGHT*SCALE) as u32),
-            Rect::new(0, 0, (WIDTH*SCALE) as u32, (HEIGHT*SCALE) as u32)
-        ).unwrap();
-        (0..HEIGHT).for_each(|y| {
-            (0..WIDTH).for_each(|x| {
-                if pixels[y * WIDTH + x] {
-                    let dst_rect = Rect::new((x * SCALE) as i32, (y * SCALE) as i32, SCALE as u32, SCALE as u32);
-                    canvas.copy(pixel, src_rect, dst_rect).unwrap();
-                }
-            })
-        });
+impl <'a> crate::arch::Display for Display<'a> {
+    fn present(&mut self, pixels: &[bool;PIXELS]) {
+        if *pixels != self.pixels {
+            self.pixels = *pixels;
+            self.dirty = true;
+        }
+        let size = self.canvas.output_size().unwrap_or(self.rendered_size);
+        // Fading pixels keep changing after the framebuffer stops
+        if !self.dirty && size == self.rendered_size && !self.phosphor.fading(&self.pixels) {
+            return;
+        }
+        self.dirty = false;
+        self.rendered_size = size;
+        let viewport = self.viewport(size);
+        self.present_background(viewport);
+        self.present_pixels(viewport);
+        self.present_run_state(viewport);
         self.canvas.present();
     }
 
+    fn show_run_state(&mut self, state: RunState) {
+        self.run_state = state;
+        self.dirty = true;
+    }
+
+    fn show_speed(&mut self, instructions_per_frame: u64) {
+        self.speed = instructions_per_frame;
+        self.update_title();
+    }
+
+    fn show_title(&mut self, title: Option<&str>) {
+        self.rom_title = title.map(String::from);
+        self.update_title();
+    }
+
+    fn show_key_hints(&mut self, hints: Option<&str>) {
+        self.key_hints = hints.map(String::from);
+        self.update_title();
+    }
+
+    fn next_theme(&mut self) {
+        self.skin = (self.skin + 1) % self.skins.len();
+        self.update_title();
+        self.dirty = true;
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off
+        };
+        let _ = window.set_fullscreen(fullscreen);
+        self.dirty = true;
+    }
+
     fn reset(&mut self) {
         self.pixels = [false;PIXELS];
+        self.phosphor.reset();
+        self.dirty = true;
     }
 }
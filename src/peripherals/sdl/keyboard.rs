@@ -1,37 +1,175 @@
-use std::{collections::HashMap, time::Duration};
+use std::{collections::{HashMap, HashSet}, time::Duration};
 
-use sdl2::{keyboard::Keycode, event::Event, Sdl, EventPump};
+use sdl2::{keyboard::{Keycode, Mod}, event::Event, pixels::Color, rect::Rect, Sdl, EventPump, VideoSubsystem};
+use sdl2::{controller::{Button, GameController}, GameControllerSubsystem};
 
 use crate::arch::NUM_KEYS;
+use crate::config::KeyBinding;
+use crate::cpu::font::FONT_DATA;
+
+const REMAP_KEY: Keycode = Keycode::F1;
+const SPEED_DOWN_KEY: Keycode = Keycode::F3;
+const SPEED_UP_KEY: Keycode = Keycode::F4;
+const PAUSE_KEY: Keycode = Keycode::F5;
+const FRAME_ADVANCE_KEY: Keycode = Keycode::F6;
+const SLOW_MOTION_KEY: Keycode = Keycode::F7;
+const THEME_KEY: Keycode = Keycode::F8;
+const MUTE_KEY: Keycode = Keycode::F9;
+const VOLUME_DOWN_KEY: Keycode = Keycode::PageDown;
+const VOLUME_UP_KEY: Keycode = Keycode::PageUp;
+const FULLSCREEN_KEY: Keycode = Keycode::F11;
+const FAST_FORWARD_KEY: Keycode = Keycode::Tab;
+const REMAP_CELL_SIZE: u32 = 32;
+// Keys with a job of their own, which can't be bound to CHIP-8 keys
+const RESERVED_KEYS: [Keycode; 16] = [
+    Keycode::Escape, Keycode::Return, Keycode::KpEnter, Keycode::Pause,
+    REMAP_KEY, SPEED_DOWN_KEY, SPEED_UP_KEY, PAUSE_KEY,
+    FRAME_ADVANCE_KEY, SLOW_MOTION_KEY, THEME_KEY, MUTE_KEY,
+    VOLUME_DOWN_KEY, VOLUME_UP_KEY, FULLSCREEN_KEY, FAST_FORWARD_KEY
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ButtonAction {
+    Key(usize),
+    Reset
+}
 
 pub struct Keyboard {
     event_pump: EventPump,
+    video: VideoSubsystem,
+    controller_subsystem: Option<GameControllerSubsystem>,
+    // Open controllers by joystick instance id
+    controllers: HashMap<u32, GameController>,
     key_states: [bool;NUM_KEYS],
+    held: HashSet<Keycode>,
+    held_buttons: HashSet<(u32, Button)>,
     reset: bool,
     power_off: bool,
+    remap: bool,
+    // Host key names of the CHIP-8 keys changed on the remap screen
+    remapped: HashMap<usize, Vec<String>>,
+    pause: bool,
+    frame_advance: bool,
+    slow_motion: bool,
+    fast_forward: bool,
+    speed_up: bool,
+    speed_down: bool,
+    theme: bool,
+    fullscreen: bool,
+    mute: bool,
+    volume_up: bool,
+    volume_down: bool,
     keymap: HashMap<Keycode, usize>,
+    buttonmap: HashMap<Button, ButtonAction>,
+    // What CHIP-8 keys do in the rom, shown while remapping
+    key_hints: HashMap<usize, Vec<String>>,
     polling_interval: Duration
 }
 
 impl Keyboard {
-    pub fn new(sdl: &Sdl) -> Option<Keyboard> {
-        let event_pump = sdl.event_pump().ok()?;
-        let mut keymap = HashMap::new();
+    pub fn default_keymap() -> HashMap<Keycode, usize> {
         let keycodes = vec![
             Keycode::Q, Keycode::W, Keycode::E, Keycode::R,
             Keycode::A, Keycode::S, Keycode::D, Keycode::F,
             Keycode::Z, Keycode::X, Keycode::C, Keycode::V,
             Keycode::Num1, Keycode::Num2, Keycode::Num3, Keycode::Num4
         ];
-        for (keycode, key) in keycodes.iter().zip(0x0..=0xF) {
-            keymap.insert(*keycode, key);
+        keycodes.into_iter().zip(0x0..=0xF).collect()
+    }
+
+    // Applies bindings of CHIP-8 keys ("0" to "F") to SDL key names on top of
+    // the default keymap. A bound CHIP-8 key loses its default host key.
+    pub fn keymap_from_names(bindings: &HashMap<String, KeyBinding>) -> Result<HashMap<Keycode, usize>, String> {
+        let mut keymap = Keyboard::default_keymap();
+        for (key, binding) in bindings {
+            let key = usize::from_str_radix(key, 16).ok()
+                .filter(|key| *key < NUM_KEYS)
+                .ok_or_else(|| format!("invalid CHIP-8 key in keymap: {} (must be 0 to F)", key))?;
+            keymap.retain(|_, mapped| *mapped != key);
+            for name in binding.names() {
+                let keycode = Keycode::from_name(name)
+                    .ok_or_else(|| format!("unknown key name in keymap: {}", name))?;
+                if RESERVED_KEYS.contains(&keycode) {
+                    return Err(format!("{} can't be in the keymap, it's a hotkey", name));
+                }
+                keymap.insert(keycode, key);
+            }
+        }
+        Ok(keymap)
+    }
+
+    // The D-pad maps to the usual 2/4/6/8 directions, A to 5 and Back resets.
+    pub fn default_buttonmap() -> HashMap<Button, ButtonAction> {
+        vec![
+            (Button::DPadUp, ButtonAction::Key(0x2)),
+            (Button::DPadLeft, ButtonAction::Key(0x4)),
+            (Button::DPadRight, ButtonAction::Key(0x6)),
+            (Button::DPadDown, ButtonAction::Key(0x8)),
+            (Button::A, ButtonAction::Key(0x5)),
+            (Button::B, ButtonAction::Key(0x0)),
+            (Button::X, ButtonAction::Key(0xA)),
+            (Button::Y, ButtonAction::Key(0xB)),
+            (Button::LeftShoulder, ButtonAction::Key(0x1)),
+            (Button::RightShoulder, ButtonAction::Key(0x3)),
+            (Button::Start, ButtonAction::Key(0xF)),
+            (Button::Back, ButtonAction::Reset)
+        ].into_iter().collect()
+    }
+
+    // Like keymap_from_names, but for SDL controller button names such as
+    // "dpup" or "leftshoulder". Besides CHIP-8 keys, "reset" can be bound.
+    pub fn buttonmap_from_names(bindings: &HashMap<String, KeyBinding>) -> Result<HashMap<Button, ButtonAction>, String> {
+        let mut buttonmap = Keyboard::default_buttonmap();
+        for (action, binding) in bindings {
+            let action = if action.eq_ignore_ascii_case("reset") {
+                ButtonAction::Reset
+            } else {
+                usize::from_str_radix(action, 16).ok()
+                    .filter(|key| *key < NUM_KEYS)
+                    .map(ButtonAction::Key)
+                    .ok_or_else(|| format!("invalid action in controller map: {} (must be 0 to F or reset)", action))?
+            };
+            buttonmap.retain(|_, mapped| *mapped != action);
+            for name in binding.names() {
+                let button = Button::from_string(name)
+                    .ok_or_else(|| format!("unknown button name in controller map: {}", name))?;
+                buttonmap.insert(button, action);
+            }
         }
+        Ok(buttonmap)
+    }
+
+    pub fn new(sdl: &Sdl, keymap: HashMap<Keycode, usize>, buttonmap: HashMap<Button, ButtonAction>) -> Option<Keyboard> {
+        let event_pump = sdl.event_pump().ok()?;
+        let video = sdl.video().ok()?;
+        // Controllers are optional, already connected ones show up as added devices
+        let controller_subsystem = sdl.game_controller().ok();
         let keyboard = Keyboard {
             event_pump,
+            video,
+            controller_subsystem,
+            controllers: HashMap::new(),
             key_states: [false;NUM_KEYS],
+            held: HashSet::new(),
+            held_buttons: HashSet::new(),
             reset: false,
             power_off: false,
+            remap: false,
+            remapped: HashMap::new(),
+            pause: false,
+            frame_advance: false,
+            slow_motion: false,
+            fast_forward: false,
+            speed_up: false,
+            speed_down: false,
+            theme: false,
+            fullscreen: false,
+            mute: false,
+            volume_up: false,
+            volume_down: false,
             keymap,
+            buttonmap,
+            key_hints: HashMap::new(),
             polling_interval: Duration::from_millis(10)
         };
         Some(keyboard)
@@ -48,24 +186,248 @@ impl Keyboard {
                 sdl2::event::Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
                     self.reset = true
                 }
+                sdl2::event::Event::KeyDown { keycode: Some(REMAP_KEY), repeat: false, .. } => {
+                    self.remap = true
+                }
+                sdl2::event::Event::KeyDown { keycode: Some(PAUSE_KEY), repeat: false, .. } |
+                sdl2::event::Event::KeyDown { keycode: Some(Keycode::Pause), repeat: false, .. } => {
+                    self.pause = true
+                }
+                sdl2::event::Event::KeyDown { keycode: Some(FRAME_ADVANCE_KEY), .. } => {
+                    self.frame_advance = true
+                }
+                sdl2::event::Event::KeyDown { keycode: Some(SLOW_MOTION_KEY), repeat: false, .. } => {
+                    self.slow_motion = true
+                }
+                sdl2::event::Event::KeyDown { keycode: Some(SPEED_UP_KEY), .. } => {
+                    self.speed_up = true
+                }
+                sdl2::event::Event::KeyDown { keycode: Some(SPEED_DOWN_KEY), .. } => {
+                    self.speed_down = true
+                }
+                sdl2::event::Event::KeyDown { keycode: Some(THEME_KEY), repeat: false, .. } => {
+                    self.theme = true
+                }
+                sdl2::event::Event::KeyDown { keycode: Some(MUTE_KEY), repeat: false, .. } => {
+                    self.mute = true
+                }
+                sdl2::event::Event::KeyDown { keycode: Some(VOLUME_UP_KEY), .. } => {
+                    self.volume_up = true
+                }
+                sdl2::event::Event::KeyDown { keycode: Some(VOLUME_DOWN_KEY), .. } => {
+                    self.volume_down = true
+                }
+                sdl2::event::Event::KeyDown { keycode: Some(FULLSCREEN_KEY), repeat: false, .. } => {
+                    self.fullscreen = true
+                }
+                sdl2::event::Event::KeyDown { keycode: Some(Keycode::Return), keymod, repeat: false, .. }
+                    if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                    self.fullscreen = true
+                }
+                sdl2::event::Event::KeyDown { keycode: Some(FAST_FORWARD_KEY), .. } => {
+                    self.fast_forward = true
+                }
+                sdl2::event::Event::KeyUp { keycode: Some(FAST_FORWARD_KEY), .. } => {
+                    self.fast_forward = false
+                }
                 sdl2::event::Event::KeyDown { keycode: Some(keycode), .. } if self.keymap.contains_key(keycode) => {
                     let key = self.keymap[keycode];
+                    self.held.insert(*keycode);
                     self.key_states[key] = true;
                     recently_pressed = Some(key as u8);
                 },
                 sdl2::event::Event::KeyUp { keycode: Some(keycode), .. } if self.keymap.contains_key(keycode) => {
-                    self.key_states[self.keymap[keycode]] = false
+                    self.held.remove(keycode);
+                    self.update_key_state(self.keymap[keycode]);
+                }
+                sdl2::event::Event::ControllerDeviceAdded { which, .. } => {
+                    self.open_controller(*which)
+                }
+                sdl2::event::Event::ControllerDeviceRemoved { which, .. } => {
+                    self.close_controller(*which)
+                }
+                sdl2::event::Event::ControllerButtonDown { which, button, .. } => {
+                    match self.buttonmap.get(button) {
+                        Some(ButtonAction::Key(key)) => {
+                            self.held_buttons.insert((*which, *button));
+                            self.key_states[*key] = true;
+                            recently_pressed = Some(*key as u8);
+                        }
+                        Some(ButtonAction::Reset) => self.reset = true,
+                        None => { }
+                    }
+                }
+                sdl2::event::Event::ControllerButtonUp { which, button, .. } => {
+                    if let Some(ButtonAction::Key(key)) = self.buttonmap.get(button) {
+                        self.held_buttons.remove(&(*which, *button));
+                        self.update_key_state(*key);
+                    }
                 }
                 _ => { }
             }
         });
+        if self.remap {
+            self.remap = false;
+            self.run_remap_screen();
+            return None;
+        }
         recently_pressed
     }
+
+    // A CHIP-8 key stays down as long as any host key or button bound to it is held.
+    fn update_key_state(&mut self, key: usize) {
+        let keymap = &self.keymap;
+        let buttonmap = &self.buttonmap;
+        self.key_states[key] = self.held.iter().any(|keycode| keymap.get(keycode) == Some(&key))
+            || self.held_buttons.iter().any(|(_, button)| buttonmap.get(button) == Some(&ButtonAction::Key(key)));
+    }
+
+    fn open_controller(&mut self, joystick_index: u32) {
+        if let Some(subsystem) = &self.controller_subsystem {
+            if let Ok(controller) = subsystem.open(joystick_index) {
+                self.controllers.insert(controller.instance_id(), controller);
+            }
+        }
+    }
+
+    fn close_controller(&mut self, instance_id: u32) {
+        self.controllers.remove(&instance_id);
+        let keys: Vec<_> = self.held_buttons.iter()
+            .filter(|(which, _)| *which == instance_id)
+            .filter_map(|(_, button)| match self.buttonmap.get(button) {
+                Some(ButtonAction::Key(key)) => Some(*key),
+                _ => None
+            })
+            .collect();
+        self.held_buttons.retain(|(which, _)| *which != instance_id);
+        keys.into_iter().for_each(|key| self.update_key_state(key));
+    }
+
+    // Opens a window asking for new host keys for each CHIP-8 key in turn, confirmed
+    // with Enter. Escape, or Enter before any key, keeps the current binding.
+    // Hotkeys are refused. Emulation is paused until all keys are done.
+    fn run_remap_screen(&mut self) {
+        let window = self.video
+            .window("koro8 keymap", 8 * REMAP_CELL_SIZE, 7 * REMAP_CELL_SIZE)
+            .position_centered()
+            .build();
+        let mut canvas = match window.map(|window| window.into_canvas().build()) {
+            Ok(Ok(canvas)) => canvas,
+            _ => return
+        };
+        self.held.clear();
+        self.held_buttons.clear();
+        self.key_states = [false;NUM_KEYS];
+
+        for key in 0..NUM_KEYS {
+            canvas.set_draw_color(Color::BLACK);
+            canvas.clear();
+            canvas.set_draw_color(Color::WHITE);
+            for (row_ix, row) in FONT_DATA[key * 5 .. key * 5 + 5].iter().enumerate() {
+                for col_ix in 0..4 {
+                    if (row >> (7 - col_ix)) & 1 == 1 {
+                        let _ = canvas.fill_rect(Rect::new(
+                            ((col_ix + 2) * REMAP_CELL_SIZE) as i32,
+                            ((row_ix as u32 + 1) * REMAP_CELL_SIZE) as i32,
+                            REMAP_CELL_SIZE,
+                            REMAP_CELL_SIZE
+                        ));
+                    }
+                }
+            }
+            canvas.present();
+
+            let mut chosen: Vec<Keycode> = Vec::new();
+            let mut refused = None;
+            loop {
+                let label = self.key_label(key);
+                let title = match (refused.take(), chosen.is_empty()) {
+                    (Some(keycode), _) => format!("koro8 keymap: {} is a hotkey, press another key for {}", Keycode::name(keycode), label),
+                    (None, true) => format!("koro8 keymap: press keys for {}, then Enter (Esc keeps {})", label, self.key_names(key).join(", ")),
+                    (None, false) => format!(
+                        "koro8 keymap: {} = {}, more keys or Enter",
+                        label,
+                        chosen.iter().map(|keycode| keycode.name()).collect::<Vec<_>>().join(", ")
+                    )
+                };
+                let _ = canvas.window_mut().set_title(&title);
+                match self.wait_remap_key() {
+                    Some(Keycode::Escape) => break,
+                    Some(Keycode::Return) | Some(Keycode::KpEnter) => {
+                        if !chosen.is_empty() {
+                            self.keymap.retain(|_, mapped| *mapped != key);
+                            self.keymap.extend(chosen.iter().map(|keycode| (*keycode, key)));
+                            self.remapped.insert(key, chosen.iter().map(|keycode| keycode.name()).collect());
+                        }
+                        break;
+                    }
+                    Some(keycode) if RESERVED_KEYS.contains(&keycode) => refused = Some(keycode),
+                    Some(keycode) => {
+                        if !chosen.contains(&keycode) {
+                            chosen.push(keycode);
+                        }
+                    }
+                    None => return
+                }
+            }
+        }
+    }
+
+    fn wait_remap_key(&mut self) -> Option<Keycode> {
+        loop {
+            for evt in self.event_pump.poll_iter() {
+                match evt {
+                    Event::Quit { .. } => {
+                        self.power_off = true;
+                        return None;
+                    }
+                    Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => return Some(keycode),
+                    _ => { }
+                }
+            }
+            std::thread::sleep(self.polling_interval);
+        }
+    }
+
+    // The CHIP-8 key with what it does in the rom, e.g. "4 (left)"
+    fn key_label(&self, key: usize) -> String {
+        match self.key_hints.get(&key) {
+            Some(hints) => format!("{:X} ({})", key, hints.join(", ")),
+            None => format!("{:X}", key)
+        }
+    }
+
+    fn key_names(&self, key: usize) -> Vec<String> {
+        let mut names: Vec<_> = self.keymap.iter()
+            .filter(|(_, mapped)| **mapped == key)
+            .map(|(keycode, _)| keycode.name())
+            .collect();
+        names.sort();
+        names
+    }
+
+    // What the rom's keys do, from crate::database::RomInfo::keys
+    pub fn set_key_hints(&mut self, keys: &[(String, u8)]) {
+        self.key_hints.clear();
+        for (name, key) in keys {
+            self.key_hints.entry(*key as usize).or_default().push(name.clone());
+        }
+    }
+
+    // The CHIP-8 keys changed on the remap screen with their new host key
+    // names, in config file form, so that they can be saved.
+    pub fn remapped_keys(&self) -> HashMap<String, KeyBinding> {
+        self.remapped.iter()
+            .map(|(key, names)| (format!("{:X}", key), KeyBinding::Many(names.clone())))
+            .collect()
+    }
 }
 
 impl crate::arch::Keyboard for Keyboard {
+    // Key states are as of the last time events were processed, which the CPU
+    // has happen between instructions often enough. Doing it here would make
+    // every EX9E and EXA1 pump all of SDL's events.
     fn pressed(&mut self, key: u8) -> bool {
-        self.process_events();
         self.key_states[key as usize]
     }
 
@@ -86,12 +448,80 @@ impl crate::arch::Keyboard for Keyboard {
     }
 
     fn power_off_signal(&mut self) -> bool {
+        // Keep the window responsive even if the rom never checks for keys
+        self.process_events();
         let power_off = self.power_off;
         self.power_off = false;
         power_off
     }
 
+    fn pause_signal(&mut self) -> bool {
+        let pause = self.pause;
+        self.pause = false;
+        pause
+    }
+
+    fn frame_advance_signal(&mut self) -> bool {
+        let frame_advance = self.frame_advance;
+        self.frame_advance = false;
+        frame_advance
+    }
+
+    fn slow_motion_signal(&mut self) -> bool {
+        let slow_motion = self.slow_motion;
+        self.slow_motion = false;
+        slow_motion
+    }
+
+    fn speed_up_signal(&mut self) -> bool {
+        let speed_up = self.speed_up;
+        self.speed_up = false;
+        speed_up
+    }
+
+    fn speed_down_signal(&mut self) -> bool {
+        let speed_down = self.speed_down;
+        self.speed_down = false;
+        speed_down
+    }
+
+    fn theme_signal(&mut self) -> bool {
+        let theme = self.theme;
+        self.theme = false;
+        theme
+    }
+
+    fn fullscreen_signal(&mut self) -> bool {
+        let fullscreen = self.fullscreen;
+        self.fullscreen = false;
+        fullscreen
+    }
+
+    fn mute_signal(&mut self) -> bool {
+        let mute = self.mute;
+        self.mute = false;
+        mute
+    }
+
+    fn volume_up_signal(&mut self) -> bool {
+        let volume_up = self.volume_up;
+        self.volume_up = false;
+        volume_up
+    }
+
+    fn volume_down_signal(&mut self) -> bool {
+        let volume_down = self.volume_down;
+        self.volume_down = false;
+        volume_down
+    }
+
+    fn fast_forward_held(&mut self) -> bool {
+        self.fast_forward
+    }
+
     fn reset(&mut self) {
+        self.held.clear();
+        self.held_buttons.clear();
         self.key_states = [false;NUM_KEYS];
     }
 }
@@ -0,0 +1,42 @@
+pub mod display;
+pub mod keyboard;
+pub mod buzzer;
+
+use std::io::{self, Write};
+
+use crossterm::{cursor, event, execute, terminal};
+
+// Puts the terminal into raw mode on an alternate screen for as long as it lives.
+pub struct Terminal {
+    keyboard_enhanced: bool
+}
+
+impl Terminal {
+    pub fn enter() -> io::Result<Terminal> {
+        terminal::enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide, terminal::Clear(terminal::ClearType::All))?;
+        // Terminals that support it can tell us when keys are released
+        let keyboard_enhanced = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if keyboard_enhanced {
+            execute!(stdout, event::PushKeyboardEnhancementFlags(event::KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+        Ok(Terminal { keyboard_enhanced })
+    }
+
+    pub fn reports_key_releases(&self) -> bool {
+        self.keyboard_enhanced
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        if self.keyboard_enhanced {
+            let _ = execute!(stdout, event::PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(stdout, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+        let _ = stdout.flush();
+    }
+}
@@ -0,0 +1,35 @@
+use std::io::Write;
+
+pub struct Buzzer;
+
+impl crate::arch::Buzzer for Buzzer {
+    fn start(&mut self, _frames: u8) {
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(b"\x07");
+        let _ = stdout.flush();
+    }
+
+    fn stop(&mut self) {
+        // the bell stops by itself
+    }
+
+    fn set_volume(&mut self, _percent: u8) {
+        // the bell is as loud as the terminal makes it
+    }
+
+    fn volume(&self) -> u8 {
+        100
+    }
+
+    fn set_muted(&mut self, _muted: bool) {
+        // use --mute instead
+    }
+
+    fn muted(&self) -> bool {
+        false
+    }
+
+    fn reset(&mut self) {
+        // nothing to do here
+    }
+}
@@ -0,0 +1,173 @@
+use std::io::{self, Stdout, Write};
+
+use crossterm::{cursor::MoveTo, queue, style::{Color, Print, SetBackgroundColor, SetForegroundColor, ResetColor}, terminal::{Clear, ClearType}};
+
+use crate::arch::{PIXELS, WIDTH, HEIGHT, RunState};
+use crate::phosphor::Phosphor;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Glyphs {
+    // Two pixels per character cell, 64x16 cells
+    HalfBlock,
+    // Eight pixels per character cell, 32x8 cells
+    Braille
+}
+
+pub struct Display {
+    out: Stdout,
+    glyphs: Glyphs,
+    foreground: Color,
+    background: Color,
+    pixels: [bool;PIXELS],
+    phosphor: Phosphor,
+    // Pixels as shown after persistence, which a character cell can only show as on or off
+    shown_pixels: [bool;PIXELS],
+    // What is currently on the terminal, so that only changed cells are written
+    shown: Vec<char>,
+    run_state: RunState,
+    speed: u64,
+    rom_title: Option<String>,
+    key_hints: Option<String>
+}
+
+impl Glyphs {
+    fn cell_size(self) -> (usize, usize) {
+        match self {
+            Glyphs::HalfBlock => (1, 2),
+            Glyphs::Braille => (2, 4)
+        }
+    }
+}
+
+impl Display {
+    pub fn new(glyphs: Glyphs, foreground: Color, background: Color, phosphor: Phosphor) -> Display {
+        let (cell_width, cell_height) = glyphs.cell_size();
+        Display {
+            out: io::stdout(),
+            glyphs,
+            foreground,
+            background,
+            pixels: [false;PIXELS],
+            phosphor,
+            shown_pixels: [false;PIXELS],
+            shown: vec!['\0'; (WIDTH / cell_width) * (HEIGHT / cell_height)],
+            run_state: RunState::Running,
+            speed: 0,
+            rom_title: None,
+            key_hints: None
+        }
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> bool {
+        self.shown_pixels[y * WIDTH + x]
+    }
+
+    fn glyph(&self, col: usize, row: usize) -> char {
+        match self.glyphs {
+            Glyphs::HalfBlock => match (self.pixel(col, row * 2), self.pixel(col, row * 2 + 1)) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' '
+            },
+            Glyphs::Braille => {
+                // Dot numbering of the Unicode braille patterns block
+                const DOTS: [(usize, usize, u32); 8] = [
+                    (0, 0, 0x01), (0, 1, 0x02), (0, 2, 0x04), (1, 0, 0x08),
+                    (1, 1, 0x10), (1, 2, 0x20), (0, 3, 0x40), (1, 3, 0x80)
+                ];
+                let bits = DOTS.iter()
+                    .filter(|(dx, dy, _)| self.pixel(col * 2 + dx, row * 4 + dy))
+                    .fold(0, |bits, (_, _, bit)| bits | bit);
+                std::char::from_u32(0x2800 + bits).unwrap_or(' ')
+            }
+        }
+    }
+
+    fn render(&mut self) -> io::Result<()> {
+        let brightness = self.phosphor.apply(&self.pixels);
+        for (shown, brightness) in self.shown_pixels.iter_mut().zip(brightness.iter()) {
+            *shown = *brightness >= 128;
+        }
+        let (cell_width, cell_height) = self.glyphs.cell_size();
+        let cols = WIDTH / cell_width;
+        queue!(self.out, SetForegroundColor(self.foreground), SetBackgroundColor(self.background))?;
+        let mut cursor = None;
+        for row in 0..HEIGHT / cell_height {
+            for col in 0..cols {
+                let glyph = self.glyph(col, row);
+                if self.shown[row * cols + col] != glyph {
+                    self.shown[row * cols + col] = glyph;
+                    if cursor != Some((col, row)) {
+                        queue!(self.out, MoveTo(col as u16, row as u16))?;
+                    }
+                    queue!(self.out, Print(glyph))?;
+                    cursor = Some((col + 1, row));
+                }
+            }
+        }
+        queue!(self.out, ResetColor)?;
+        self.out.flush()
+    }
+
+    fn render_status(&mut self) -> io::Result<()> {
+        let (_, cell_height) = self.glyphs.cell_size();
+        let state = match self.run_state {
+            RunState::Running => "",
+            RunState::Paused => " | paused",
+            RunState::FastForward => " | fast-forward",
+            RunState::SlowMotion => " | slow motion"
+        };
+        queue!(
+            self.out,
+            MoveTo(0, (HEIGHT / cell_height) as u16),
+            Clear(ClearType::CurrentLine),
+            Print(match (&self.rom_title, &self.key_hints) {
+                (Some(title), Some(hints)) => format!("koro8 | {} | {} instructions/frame{} | keys: {}", title, self.speed, state, hints),
+                (Some(title), None) => format!("koro8 | {} | {} instructions/frame{}", title, self.speed, state),
+                (None, _) => format!("koro8 | {} instructions/frame{}", self.speed, state)
+            })
+        )?;
+        self.out.flush()
+    }
+}
+
+impl crate::arch::Display for Display {
+    fn present(&mut self, pixels: &[bool;PIXELS]) {
+        self.pixels = *pixels;
+        let _ = self.render();
+    }
+
+    fn show_run_state(&mut self, state: RunState) {
+        self.run_state = state;
+        let _ = self.render_status();
+    }
+
+    fn show_speed(&mut self, instructions_per_frame: u64) {
+        self.speed = instructions_per_frame;
+        let _ = self.render_status();
+    }
+
+    fn show_title(&mut self, title: Option<&str>) {
+        self.rom_title = title.map(String::from);
+        let _ = self.render_status();
+    }
+
+    fn show_key_hints(&mut self, hints: Option<&str>) {
+        self.key_hints = hints.map(String::from);
+        let _ = self.render_status();
+    }
+
+    fn next_theme(&mut self) {
+        // colours are picked on the command line
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        // that's up to the terminal
+    }
+
+    fn reset(&mut self) {
+        self.pixels = [false;PIXELS];
+        self.phosphor.reset();
+    }
+}
@@ -0,0 +1,225 @@
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+
+use crate::arch::NUM_KEYS;
+use crate::config::KeyBinding;
+
+// Most terminals only report key presses and repeats. Without a release event,
+// a key counts as held until it should have repeated if it still were.
+const INITIAL_HOLD: Duration = Duration::from_millis(500);
+const REPEAT_HOLD: Duration = Duration::from_millis(100);
+
+const SPEED_DOWN_KEY: KeyCode = KeyCode::F(3);
+const SPEED_UP_KEY: KeyCode = KeyCode::F(4);
+const PAUSE_KEY: KeyCode = KeyCode::F(5);
+const FRAME_ADVANCE_KEY: KeyCode = KeyCode::F(6);
+const SLOW_MOTION_KEY: KeyCode = KeyCode::F(7);
+const FAST_FORWARD_KEY: KeyCode = KeyCode::Tab;
+
+pub struct Keyboard {
+    // Host keys that are down, with the time they are considered released
+    // unless the terminal reports releases.
+    held: HashMap<KeyCode, Option<Instant>>,
+    reports_releases: bool,
+    reset: bool,
+    power_off: bool,
+    pause: bool,
+    frame_advance: bool,
+    slow_motion: bool,
+    speed_up: bool,
+    speed_down: bool,
+    keymap: HashMap<KeyCode, usize>,
+    polling_interval: Duration
+}
+
+impl Keyboard {
+    pub fn default_keymap() -> HashMap<KeyCode, usize> {
+        "qwerasdfzxcv1234".chars().map(KeyCode::Char).zip(0x0..=0xF).collect()
+    }
+
+    // Applies bindings of CHIP-8 keys ("0" to "F") to key names on top of the
+    // default keymap. Names are single characters or ones like "Space" and "Left".
+    pub fn keymap_from_names(bindings: &HashMap<String, KeyBinding>) -> Result<HashMap<KeyCode, usize>, String> {
+        let mut keymap = Keyboard::default_keymap();
+        for (key, binding) in bindings {
+            let key = usize::from_str_radix(key, 16).ok()
+                .filter(|key| *key < NUM_KEYS)
+                .ok_or_else(|| format!("invalid CHIP-8 key in keymap: {} (must be 0 to F)", key))?;
+            keymap.retain(|_, mapped| *mapped != key);
+            for name in binding.names() {
+                let keycode = Keyboard::keycode_from_name(name)
+                    .ok_or_else(|| format!("unknown key name in keymap: {}", name))?;
+                keymap.insert(keycode, key);
+            }
+        }
+        Ok(keymap)
+    }
+
+    fn keycode_from_name(name: &str) -> Option<KeyCode> {
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => return Some(KeyCode::Char(c.to_ascii_lowercase())),
+            (None, _) => return None,
+            _ => { }
+        }
+        match name.to_ascii_lowercase().as_str() {
+            "space" => Some(KeyCode::Char(' ')),
+            "return" | "enter" => Some(KeyCode::Enter),
+            "backspace" => Some(KeyCode::Backspace),
+            "up" => Some(KeyCode::Up),
+            "down" => Some(KeyCode::Down),
+            "left" => Some(KeyCode::Left),
+            "right" => Some(KeyCode::Right),
+            _ => None
+        }
+    }
+
+    pub fn new(keymap: HashMap<KeyCode, usize>, reports_releases: bool) -> Keyboard {
+        Keyboard {
+            held: HashMap::new(),
+            reports_releases,
+            reset: false,
+            power_off: false,
+            pause: false,
+            frame_advance: false,
+            slow_motion: false,
+            speed_up: false,
+            speed_down: false,
+            keymap,
+            polling_interval: Duration::from_millis(10)
+        }
+    }
+
+    // Returns the CHIP-8 key of the most recent key press, if any.
+    fn process_events(&mut self) -> Option<u8> {
+        let mut recently_pressed = None;
+        while let Ok(true) = event::poll(Duration::ZERO) {
+            match event::read() {
+                Ok(Event::Key(key_event)) => {
+                    if let Some(key) = self.process_key(key_event) {
+                        recently_pressed = Some(key);
+                    }
+                }
+                Ok(_) => { }
+                Err(_) => break
+            }
+        }
+        let now = Instant::now();
+        self.held.retain(|_, until| match until {
+            Some(until) => *until > now,
+            None => true
+        });
+        recently_pressed
+    }
+
+    fn process_key(&mut self, key_event: KeyEvent) -> Option<u8> {
+        let code = match key_event.code {
+            KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+            code => code
+        };
+        // The only way out, so it must neither pass for a repeat nor press CHIP-8 key A
+        if code == KeyCode::Char('c') && key_event.modifiers.contains(KeyModifiers::CONTROL) {
+            if key_event.kind != KeyEventKind::Release {
+                self.power_off = true;
+            }
+            return None;
+        }
+        if key_event.kind == KeyEventKind::Release {
+            self.held.remove(&code);
+            return None;
+        }
+        let repeat = self.held.contains_key(&code) || key_event.kind == KeyEventKind::Repeat;
+        let until = if self.reports_releases {
+            None
+        } else if repeat {
+            Some(Instant::now() + REPEAT_HOLD)
+        } else {
+            Some(Instant::now() + INITIAL_HOLD)
+        };
+        self.held.insert(code, until);
+        if repeat {
+            return None;
+        }
+        match code {
+            KeyCode::Esc => self.reset = true,
+            SPEED_DOWN_KEY => self.speed_down = true,
+            SPEED_UP_KEY => self.speed_up = true,
+            PAUSE_KEY => self.pause = true,
+            FRAME_ADVANCE_KEY => self.frame_advance = true,
+            SLOW_MOTION_KEY => self.slow_motion = true,
+            _ => return self.keymap.get(&code).map(|key| *key as u8)
+        }
+        None
+    }
+}
+
+impl crate::arch::Keyboard for Keyboard {
+    // Held keys are as of the last poll, in power_off_signal once per frame or
+    // in wait_key. Polling here would have every EX9E and EXA1 read the terminal.
+    fn pressed(&mut self, key: u8) -> bool {
+        let keymap = &self.keymap;
+        self.held.keys().any(|code| keymap.get(code) == Some(&(key as usize)))
+    }
+
+    fn wait_key(&mut self) -> u8 {
+        while !self.reset && !self.power_off {
+            match self.process_events() {
+                Some(key) => return key,
+                None => std::thread::sleep(self.polling_interval)
+            }
+        }
+        0
+    }
+
+    fn reset_signal(&mut self) -> bool {
+        let reset = self.reset;
+        self.reset = false;
+        reset
+    }
+
+    fn power_off_signal(&mut self) -> bool {
+        self.process_events();
+        let power_off = self.power_off;
+        self.power_off = false;
+        power_off
+    }
+
+    fn pause_signal(&mut self) -> bool {
+        let pause = self.pause;
+        self.pause = false;
+        pause
+    }
+
+    fn frame_advance_signal(&mut self) -> bool {
+        let frame_advance = self.frame_advance;
+        self.frame_advance = false;
+        frame_advance
+    }
+
+    fn slow_motion_signal(&mut self) -> bool {
+        let slow_motion = self.slow_motion;
+        self.slow_motion = false;
+        slow_motion
+    }
+
+    fn speed_up_signal(&mut self) -> bool {
+        let speed_up = self.speed_up;
+        self.speed_up = false;
+        speed_up
+    }
+
+    fn speed_down_signal(&mut self) -> bool {
+        let speed_down = self.speed_down;
+        self.speed_down = false;
+        speed_down
+    }
+
+    fn fast_forward_held(&mut self) -> bool {
+        self.held.contains_key(&FAST_FORWARD_KEY)
+    }
+
+    fn reset(&mut self) {
+        self.held.clear();
+    }
+}
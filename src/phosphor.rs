@@ -0,0 +1,75 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::arch::PIXELS;
+
+// How pixels that were turned off linger on screen. CHIP-8 games erase and
+// redraw sprites with XOR, which flickers badly unless the display smooths it over.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Persistence {
+    // Pixels go dark as soon as they're turned off
+    Off,
+    // Pixels fade out over a number of frames, like a phosphor screen
+    Fade,
+    // A pixel is lit if it was lit in this frame or the one before
+    MaxOfTwo
+}
+
+impl FromStr for Persistence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Persistence, String> {
+        match s {
+            "off" => Ok(Persistence::Off),
+            "fade" => Ok(Persistence::Fade),
+            "max-of-two" => Ok(Persistence::MaxOfTwo),
+            _ => Err(String::from("must be off, fade or max-of-two"))
+        }
+    }
+}
+
+// Turns the framebuffer into pixel brightnesses, once per presented frame,
+// so that every display backend can offer the same anti-flicker modes.
+pub struct Phosphor {
+    persistence: Persistence,
+    step: u8,
+    brightness: [u8;PIXELS]
+}
+
+impl Phosphor {
+    // With fading, a pixel takes `decay_frames` frames to go dark.
+    pub fn new(persistence: Persistence, decay_frames: u8) -> Phosphor {
+        Phosphor {
+            persistence,
+            step: (255 / std::cmp::max(1, decay_frames) as u16) as u8,
+            brightness: [0;PIXELS]
+        }
+    }
+
+    // Advances by a frame and returns the brightness of each pixel, from 0 to 255.
+    pub fn apply(&mut self, pixels: &[bool;PIXELS]) -> &[u8;PIXELS] {
+        let persistence = self.persistence;
+        let step = self.step;
+        for (brightness, lit) in self.brightness.iter_mut().zip(pixels.iter()) {
+            *brightness = match (persistence, *lit) {
+                (_, true) => 255,
+                (Persistence::Off, false) => 0,
+                (Persistence::Fade, false) => brightness.saturating_sub(step),
+                // Full brightness means lit in the previous frame
+                (Persistence::MaxOfTwo, false) => if *brightness == 255 { 254 } else { 0 }
+            };
+        }
+        &self.brightness
+    }
+
+    // Whether the next frame could differ from the last one even if no pixels change.
+    pub fn fading(&self, pixels: &[bool;PIXELS]) -> bool {
+        self.brightness.iter().zip(pixels.iter()).any(|(brightness, lit)| *brightness > 0 && !lit)
+    }
+
+    pub fn reset(&mut self) {
+        self.brightness = [0;PIXELS];
+    }
+}
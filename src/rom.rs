@@ -0,0 +1,7 @@
+use sha1::{Digest, Sha1};
+
+// Roms are identified by the hex encoded SHA-1 hash of their contents,
+// like in the community chip-8 database.
+pub fn hash(rom: &[u8]) -> String {
+    format!("{:x}", Sha1::digest(rom))
+}
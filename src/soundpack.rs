@@ -0,0 +1,230 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, str::FromStr, time::Duration};
+
+use rand::{Rng, RngCore};
+use serde::Deserialize;
+
+use crate::arch::Event;
+use crate::assets;
+use crate::config::ConfigError;
+
+// Name of the manifest in a sound directory
+pub const MANIFEST: &str = "sounds.toml";
+// Least time between two sounds for the same event, unless the manifest says otherwise
+const EVENT_COOLDOWN_MILLIS: u64 = 500;
+
+// How the next clip is picked when a sound starts
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Selection {
+    // At random by weight, never the same clip twice in a row
+    Shuffle,
+    // Every clip in turn, in manifest order
+    RoundRobin,
+    // Like shuffle, among the clips meant for the sound timer value
+    Duration
+}
+
+// A sound in a pack, as given in the manifest.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Clip {
+    // Relative to the sound directory
+    pub file: PathBuf,
+    // Relative chance of being picked
+    #[serde(default = "Clip::default_weight")]
+    pub weight: u32,
+    pub group: Option<String>,
+    // In percent of the buzzer volume
+    pub volume: Option<u8>,
+    // Range of sound timer values, in frames, the clip is meant for. Taken
+    // from the group if not given.
+    pub duration: Option<(u8, u8)>
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct Group {
+    duration: Option<(u8, u8)>
+}
+
+// One or more files, e.g. `collision = "sneeze.mp3"` or `collision = ["sneeze.mp3", "yes.mp3"]`.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Files {
+    One(PathBuf),
+    Many(Vec<PathBuf>)
+}
+
+// What a sound directory's sounds.toml can contain. A directory without one
+// is a pack of all its files, shuffled.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default = "Manifest::default_selection")]
+    selection: Selection,
+    #[serde(default)]
+    groups: HashMap<String, Group>,
+    #[serde(default)]
+    clips: Vec<Clip>,
+    // Event names, see crate::arch::Event, to the clips to pick from when they happen
+    #[serde(default)]
+    events: HashMap<String, Files>,
+    event_cooldown: Option<u64>
+}
+
+pub struct SoundPack {
+    // Where the clips are, or None for the built-in sounds
+    pub dir: Option<PathBuf>,
+    pub selection: Selection,
+    pub clips: Vec<Clip>,
+    // Files to play when events happen, not limited to the clips
+    pub events: HashMap<Event, Vec<PathBuf>>,
+    pub event_cooldown: Duration,
+    last: Option<usize>,
+    next: usize
+}
+
+impl FromStr for Selection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Selection, String> {
+        match s {
+            "shuffle" => Ok(Selection::Shuffle),
+            "round-robin" => Ok(Selection::RoundRobin),
+            "duration" => Ok(Selection::Duration),
+            _ => Err(String::from("must be shuffle, round-robin or duration"))
+        }
+    }
+}
+
+impl Clip {
+    fn default_weight() -> u32 {
+        1
+    }
+
+    pub fn suits(&self, frames: u8) -> bool {
+        matches!(self.duration, Some((min, max)) if (min..=max).contains(&frames))
+    }
+}
+
+impl Manifest {
+    fn default_selection() -> Selection {
+        Selection::Shuffle
+    }
+
+    fn into_pack(self, dir: Option<PathBuf>) -> Result<SoundPack, String> {
+        let groups = self.groups;
+        // Clips with the durations of their groups filled in
+        let clips = self.clips.into_iter().map(|mut clip| {
+            if let Some(name) = &clip.group {
+                let group = groups.get(name).ok_or_else(|| format!("unknown group {} for {}", name, clip.file.display()))?;
+                clip.duration = clip.duration.or(group.duration);
+            }
+            Ok(clip)
+        }).collect::<Result<_, String>>()?;
+        let events = self.events.into_iter().map(|(name, files)| {
+            let files = match files {
+                Files::One(file) => vec![file],
+                Files::Many(files) => files
+            };
+            Ok((name.parse()?, files))
+        }).collect::<Result<_, String>>()?;
+        let mut pack = SoundPack::new(dir, self.selection, clips);
+        pack.events = events;
+        pack.event_cooldown = Duration::from_millis(self.event_cooldown.unwrap_or(EVENT_COOLDOWN_MILLIS));
+        Ok(pack)
+    }
+}
+
+impl SoundPack {
+    pub fn bundled() -> Option<SoundPack> {
+        let manifest = std::str::from_utf8(assets::sound_file(MANIFEST)?).ok()?;
+        let manifest: Manifest = toml::from_str(manifest).ok()?;
+        manifest.into_pack(None).ok()
+    }
+
+    pub fn load(dir: &Path) -> Result<SoundPack, ConfigError> {
+        let manifest_path = dir.join(MANIFEST);
+        if manifest_path.exists() {
+            let contents = std::fs::read_to_string(&manifest_path)
+                .map_err(|err| ConfigError::Io(manifest_path.clone(), err))?;
+            let manifest: Manifest = toml::from_str(&contents)
+                .map_err(|err| ConfigError::Parse(manifest_path.clone(), err))?;
+            return manifest.into_pack(Some(dir.to_path_buf()))
+                .map_err(|reason| ConfigError::Invalid(manifest_path, reason));
+        }
+        let entries = std::fs::read_dir(dir).map_err(|err| ConfigError::Io(dir.to_path_buf(), err))?;
+        let mut files: Vec<_> = entries.filter_map(|entry| entry.ok())
+            .map(|entry| PathBuf::from(entry.file_name()))
+            .collect();
+        files.sort();
+        let clips = files.into_iter()
+            .map(|file| Clip { file, weight: 1, group: None, volume: None, duration: None })
+            .collect();
+        Ok(SoundPack::new(Some(dir.to_path_buf()), Selection::Shuffle, clips))
+    }
+
+    fn new(dir: Option<PathBuf>, selection: Selection, clips: Vec<Clip>) -> SoundPack {
+        SoundPack {
+            dir,
+            selection,
+            clips,
+            events: HashMap::new(),
+            event_cooldown: Duration::from_millis(EVENT_COOLDOWN_MILLIS),
+            last: None,
+            next: 0
+        }
+    }
+
+    // Keeps only the clips for which `keep` is true, e.g. the ones that could be loaded.
+    pub fn retain<F: FnMut(usize, &Clip) -> bool>(&mut self, mut keep: F) {
+        let mut ix = 0;
+        self.clips.retain(|clip| {
+            ix += 1;
+            keep(ix - 1, clip)
+        });
+        self.last = None;
+        self.next = 0;
+    }
+
+    // Index of the clip to play for a sound timer value of `frames`.
+    pub fn choose(&mut self, frames: u8, rng: &mut dyn RngCore) -> Option<usize> {
+        if self.clips.is_empty() {
+            return None;
+        }
+        let choice = match self.selection {
+            Selection::RoundRobin => {
+                let choice = self.next % self.clips.len();
+                self.next = choice + 1;
+                choice
+            }
+            Selection::Shuffle => self.shuffle(|_| true, rng),
+            Selection::Duration => {
+                let any_suits = self.clips.iter().any(|clip| clip.suits(frames));
+                self.shuffle(|clip| !any_suits || clip.suits(frames), rng)
+            }
+        };
+        self.last = Some(choice);
+        Some(choice)
+    }
+
+    fn shuffle<F: Fn(&Clip) -> bool>(&self, eligible: F, rng: &mut dyn RngCore) -> usize {
+        let mut candidates: Vec<_> = (0..self.clips.len()).filter(|ix| eligible(&self.clips[*ix])).collect();
+        if candidates.len() > 1 {
+            candidates.retain(|ix| Some(*ix) != self.last);
+        }
+        let total: u64 = candidates.iter().map(|ix| self.clips[*ix].weight as u64).sum();
+        if total == 0 {
+            return candidates[rng.gen_range(0..candidates.len())];
+        }
+        let mut pick = rng.gen_range(0..total);
+        for ix in &candidates {
+            let weight = self.clips[*ix].weight as u64;
+            if pick < weight {
+                return *ix;
+            }
+            pick -= weight;
+        }
+        candidates[candidates.len() - 1]
+    }
+}
@@ -0,0 +1,130 @@
+use std::{convert::TryFrom, path::{Path, PathBuf}};
+
+use serde::Deserialize;
+
+use crate::assets;
+use crate::config::ConfigError;
+
+// Name of the manifest in a theme directory
+pub const MANIFEST: &str = "theme.toml";
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(try_from = "String")]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+// What a theme directory's theme.toml can contain. A directory without one
+// is a theme made of its pixel.png and background.png.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct Manifest {
+    title: Option<String>,
+    // Images relative to the theme directory
+    pixel: Option<PathBuf>,
+    background: Option<PathBuf>,
+    scale: Option<usize>,
+    // Colours as "#rrggbb": the background, then one for each plane, then one
+    // for pixels lit in both planes of two-plane modes
+    palette: Vec<Rgb>
+}
+
+#[derive(Clone, Debug)]
+pub enum Image {
+    File(PathBuf),
+    Embedded(&'static [u8])
+}
+
+#[derive(Clone, Debug)]
+pub struct Theme {
+    pub name: String,
+    pub title: String,
+    // Pixel sprite, or plain squares in the foreground colour if none
+    pub pixel: Option<Image>,
+    // Background image, or the background colour if none
+    pub background: Option<Image>,
+    // Preferred size of a CHIP-8 pixel on screen
+    pub scale: Option<usize>,
+    // Background and foreground, then the colours of two-plane modes, which are
+    // kept for when such modes are emulated but not used yet
+    pub palette: Vec<Rgb>
+}
+
+impl TryFrom<String> for Rgb {
+    type Error = String;
+
+    fn try_from(colour: String) -> Result<Rgb, String> {
+        let hex = colour.strip_prefix('#')
+            .filter(|hex| hex.len() == 6)
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| format!("invalid colour {} (must be like \"#ffcc00\")", colour))?;
+        Ok(Rgb((hex >> 16) as u8, (hex >> 8) as u8, hex as u8))
+    }
+}
+
+// Names of the themes built into koro8, see crate::assets.
+pub fn bundled_names() -> impl Iterator<Item = &'static str> {
+    assets::THEMES.iter().map(|(name, _)| *name)
+}
+
+// The theme used unless another one is picked
+pub fn default_name() -> &'static str {
+    assets::THEMES[0].0
+}
+
+impl Theme {
+    // The bundled theme of the given name, or else the theme directory at that path.
+    pub fn find(name_or_path: &str) -> Result<Theme, ConfigError> {
+        match Theme::bundled(name_or_path) {
+            Some(theme) => Ok(theme),
+            None => Theme::load(Path::new(name_or_path))
+        }
+    }
+
+    pub fn bundled(name: &str) -> Option<Theme> {
+        let manifest = assets::theme_file(name, MANIFEST)?;
+        let manifest: Manifest = toml::from_str(std::str::from_utf8(manifest).ok()?).ok()?;
+        let image = |file: PathBuf| assets::theme_file(name, file.to_str()?).map(Image::Embedded);
+        Some(Theme {
+            title: manifest.title.unwrap_or_else(|| String::from("koro8")),
+            pixel: manifest.pixel.and_then(image),
+            background: manifest.background.and_then(image),
+            scale: manifest.scale,
+            palette: manifest.palette,
+            name: String::from(name)
+        })
+    }
+
+    pub fn load(dir: &Path) -> Result<Theme, ConfigError> {
+        let manifest_path = dir.join(MANIFEST);
+        let manifest = if manifest_path.exists() {
+            let contents = std::fs::read_to_string(&manifest_path)
+                .map_err(|err| ConfigError::Io(manifest_path.clone(), err))?;
+            toml::from_str(&contents).map_err(|err| ConfigError::Parse(manifest_path.clone(), err))?
+        } else if dir.is_dir() {
+            Manifest {
+                pixel: Some(PathBuf::from("pixel.png")),
+                background: Some(PathBuf::from("background.png")),
+                ..Manifest::default()
+            }
+        } else {
+            let err = std::io::Error::new(std::io::ErrorKind::NotFound, "no such theme directory");
+            return Err(ConfigError::Io(dir.to_path_buf(), err));
+        };
+        let name = dir.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        Ok(Theme {
+            title: manifest.title.unwrap_or_else(|| String::from("koro8")),
+            pixel: manifest.pixel.map(|pixel| Image::File(dir.join(pixel))),
+            background: manifest.background.map(|background| Image::File(dir.join(background))),
+            scale: manifest.scale,
+            palette: manifest.palette,
+            name
+        })
+    }
+
+    pub fn background_colour(&self) -> Rgb {
+        self.palette.first().copied().unwrap_or(Rgb(0, 0, 0))
+    }
+
+    pub fn foreground_colour(&self) -> Rgb {
+        self.palette.get(1).copied().unwrap_or(Rgb(0xFF, 0xFF, 0xFF))
+    }
+}